@@ -5,6 +5,8 @@
   const state = {
     mailbox: "",
     email: "",
+    mailboxToken: "",
+    mailboxTokenDeclined: false,
     messages: [],
     currentMessageID: "",
     currentMessage: null,
@@ -103,6 +105,8 @@
   function useMailbox(mailbox) {
     state.mailbox = mailbox;
     state.email = `${mailbox}@${window.location.hostname}`;
+    state.mailboxToken = localStorage.getItem(`mailboxToken:${mailbox}`) || "";
+    state.mailboxTokenDeclined = false;
     state.currentMessageID = "";
     state.currentMessage = null;
     localStorage.setItem("mailbox", mailbox);
@@ -193,7 +197,11 @@
     while (!controller.signal.aborted && state.mailbox) {
       const endpoint = `/api/mailboxes/${encodeURIComponent(state.mailbox)}/events/next`;
       try {
-        const response = await fetch(endpoint, { cache: "no-store", signal: controller.signal });
+        const response = await fetch(endpoint, {
+          cache: "no-store",
+          signal: controller.signal,
+          headers: mailboxHeaders(),
+        });
         if (response.status === 204) {
           continue;
         }
@@ -281,7 +289,7 @@
       row.innerHTML = `
         <td>${escapeHTML(message.from || "-")}</td>
         <td>${escapeHTML(message.subject || "无主题")}</td>
-        <td>${formatDate(message.date || message.received_at)}</td>
+        <td>${formatDate(message.date || message.received_at || message.receivedAt)}</td>
       `;
 
       row.addEventListener("click", () => {
@@ -361,12 +369,12 @@
 
     const subject = message.subject || "无主题";
     const from = message.from || "-";
-    const date = formatDate(message.date || message.received_at);
+    const date = formatDate(message.date || message.received_at || message.receivedAt);
     elements.messageMeta.textContent = `${from} · ${date}`;
 
     const text = message.text || "(无纯文本内容)";
     const html = message.html || "<p style=\"padding:12px;color:#64748b;\">无 HTML 内容</p>";
-    elements.textView.textContent = `主题: ${subject}\n发件人: ${from}\n收件人: ${message.to || "-"}\n时间: ${date}\n\n${text}`;
+    elements.textView.textContent = `主题: ${subject}\n发件人: ${from}\n收件人: ${message.envelope_to || message.envelopeTo || message.to || "-"}\n时间: ${date}\n\n${text}`;
     elements.htmlView.srcdoc = html;
     elements.jsonView.textContent = JSON.stringify(message, null, 2);
     switchTab(state.activeTab);
@@ -404,9 +412,34 @@
     return `mail${Math.random().toString(36).slice(2, 10)}`;
   }
 
-  async function fetchJSON(url, options) {
-    const response = await fetch(url, { cache: "no-store", ...(options || {}) });
+  function mailboxHeaders() {
+    return state.mailboxToken ? { "X-Mailbox-Token": state.mailboxToken } : {};
+  }
+
+  function promptMailboxToken() {
+    if (state.mailboxTokenDeclined) {
+      return false;
+    }
+    const token = (window.prompt("该邮箱需要访问令牌（X-Mailbox-Token）：") || "").trim();
+    if (!token) {
+      state.mailboxTokenDeclined = true;
+      return false;
+    }
+    state.mailboxToken = token;
+    localStorage.setItem(`mailboxToken:${state.mailbox}`, token);
+    return true;
+  }
+
+  async function fetchJSON(url, options, retried) {
+    const response = await fetch(url, {
+      cache: "no-store",
+      ...(options || {}),
+      headers: mailboxHeaders(),
+    });
     const payload = await response.json().catch(() => ({}));
+    if (response.status === 401 && !retried && promptMailboxToken()) {
+      return fetchJSON(url, options, true);
+    }
     if (!response.ok) {
       throw new Error(payload.error || `请求失败: ${response.status}`);
     }
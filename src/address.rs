@@ -1,10 +1,48 @@
-use once_cell::sync::Lazy;
-use regex::Regex;
+use std::fmt;
 
-static MAILBOX_PATTERN: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^[a-z0-9][a-z0-9._+\-]{0,63}$").expect("valid mailbox regex"));
+pub const MAX_MAILBOX_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxError {
+    Empty,
+    InvalidStart(char),
+    InvalidCharacter(char),
+    TooLong(usize),
+}
+
+impl fmt::Display for MailboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailboxError::Empty => write!(f, "mailbox is empty"),
+            MailboxError::InvalidStart(ch) => {
+                write!(f, "mailbox must start with a letter or digit, got {:?}", ch)
+            }
+            MailboxError::InvalidCharacter(ch) => {
+                write!(f, "mailbox contains invalid character {:?}", ch)
+            }
+            MailboxError::TooLong(len) => write!(
+                f,
+                "mailbox is {} characters, limit is {}",
+                len, MAX_MAILBOX_LEN
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MailboxOptions<'a> {
+    pub domain: &'a str,
+    pub display_domain: &'a str,
+    pub ignore_local_dots: bool,
+    pub case_sensitive: bool,
+    pub truncate_long: bool,
+}
 
 pub fn parse_email(input: &str) -> Result<(String, String), String> {
+    split_email(input, &MailboxOptions::default())
+}
+
+fn split_email(input: &str, options: &MailboxOptions<'_>) -> Result<(String, String), String> {
     let value = normalize(input);
     let at = value
         .rfind('@')
@@ -13,10 +51,9 @@ pub fn parse_email(input: &str) -> Result<(String, String), String> {
         return Err("invalid email address".to_string());
     }
 
-    let mailbox = value[..at].trim().to_ascii_lowercase();
+    let mailbox = checked_local_part(&value[..at], options)?;
     let domain = value[at + 1..].trim().to_ascii_lowercase();
 
-    validate_mailbox(&mailbox)?;
     if domain.is_empty() {
         return Err("invalid email domain".to_string());
     }
@@ -24,35 +61,76 @@ pub fn parse_email(input: &str) -> Result<(String, String), String> {
     Ok((mailbox, domain))
 }
 
-pub fn normalize_mailbox(input: &str, expected_domain: &str) -> Result<(String, String), String> {
+pub fn normalize_mailbox(
+    input: &str,
+    options: &MailboxOptions<'_>,
+) -> Result<(String, String), String> {
     let value = normalize(input);
-    let expected_domain = expected_domain.trim().to_ascii_lowercase();
+    let expected_domain = options.domain.trim().to_ascii_lowercase();
 
     if value.contains('@') {
-        let (mailbox, domain) = parse_email(&value)?;
+        let (mailbox, domain) = split_email(&value, options)?;
         if !expected_domain.is_empty() && domain != expected_domain {
             return Err(format!("email domain must be {}", expected_domain));
         }
-        return Ok((mailbox.clone(), format!("{}@{}", mailbox, domain)));
+        let email = format!("{}@{}", mailbox, domain);
+        return Ok((canonical_mailbox(mailbox, options), email));
     }
 
-    let mailbox = value.trim().to_ascii_lowercase();
-    validate_mailbox(&mailbox)?;
+    let mailbox = checked_local_part(&value, options)?;
 
-    if expected_domain.is_empty() {
-        Ok((mailbox.clone(), mailbox))
+    let display_domain = options.display_domain.trim().to_ascii_lowercase();
+    let email = if !expected_domain.is_empty() {
+        format!("{}@{}", mailbox, expected_domain)
+    } else if !display_domain.is_empty() {
+        format!("{}@{}", mailbox, display_domain)
     } else {
-        Ok((mailbox.clone(), format!("{}@{}", mailbox, expected_domain)))
-    }
+        mailbox.clone()
+    };
+    Ok((canonical_mailbox(mailbox, options), email))
 }
 
-pub fn validate_mailbox(mailbox: &str) -> Result<(), String> {
-    if !MAILBOX_PATTERN.is_match(mailbox) {
-        return Err("invalid mailbox".to_string());
+pub fn validate_mailbox(mailbox: &str) -> Result<(), MailboxError> {
+    let mut chars = mailbox.chars();
+    let first = chars.next().ok_or(MailboxError::Empty)?;
+    if !first.is_ascii_alphanumeric() {
+        return Err(MailboxError::InvalidStart(first));
+    }
+    if let Some(ch) =
+        chars.find(|ch| !ch.is_ascii_alphanumeric() && !matches!(ch, '.' | '_' | '+' | '-'))
+    {
+        return Err(MailboxError::InvalidCharacter(ch));
+    }
+    if mailbox.len() > MAX_MAILBOX_LEN {
+        return Err(MailboxError::TooLong(mailbox.len()));
     }
     Ok(())
 }
 
+fn checked_local_part(value: &str, options: &MailboxOptions<'_>) -> Result<String, String> {
+    let value = value.trim();
+    let mut mailbox = if options.case_sensitive {
+        value.to_string()
+    } else {
+        value.to_ascii_lowercase()
+    };
+    match validate_mailbox(&mailbox) {
+        Ok(()) => {}
+        // Only ASCII survives validation, so byte truncation is safe here.
+        Err(MailboxError::TooLong(_)) if options.truncate_long => mailbox.truncate(MAX_MAILBOX_LEN),
+        Err(err) => return Err(err.to_string()),
+    }
+    Ok(mailbox)
+}
+
+fn canonical_mailbox(mailbox: String, options: &MailboxOptions<'_>) -> String {
+    if options.ignore_local_dots {
+        mailbox.replace('.', "")
+    } else {
+        mailbox
+    }
+}
+
 fn normalize(input: &str) -> String {
     input
         .trim()
@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::store::{StoreEvent, StoreEventType};
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    event: &'a StoreEventType,
+    mailbox: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<&'a str>,
+    at: DateTime<Utc>,
+}
+
+// Written in place of events the receiver fell too far behind to see, so
+// gaps show up in the log itself.
+#[derive(Debug, Serialize)]
+struct LaggedRecord {
+    event: &'static str,
+    skipped: u64,
+    at: DateTime<Utc>,
+}
+
+struct AuditFile {
+    path: PathBuf,
+    max_bytes: u64,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl AuditFile {
+    async fn open(path: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    async fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.max_bytes > 0 && self.size + line.len() as u64 > self.max_bytes && self.size > 0 {
+            self.rotate().await?;
+        }
+        self.writer.write_all(line).await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush().await?;
+        tokio::fs::rename(&self.path, rotated_path(&self.path)).await?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+pub async fn run(
+    path: PathBuf,
+    max_bytes: u64,
+    mut events: broadcast::Receiver<StoreEvent>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut file = AuditFile::open(path, max_bytes).await?;
+    info!("audit log writing to {}", file.path.display());
+    let mut flush_ticker = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            received = events.recv() => {
+                let mut line = match received {
+                    Ok(event) => serde_json::to_vec(&AuditRecord {
+                        event: &event.event,
+                        mailbox: &event.mailbox,
+                        message_id: event.message_id.as_deref(),
+                        from: event.from.as_deref(),
+                        subject: event.subject.as_deref(),
+                        at: event.at,
+                    })?,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("audit log lagged, {} events dropped", skipped);
+                        serde_json::to_vec(&LaggedRecord {
+                            event: "lagged",
+                            skipped,
+                            at: Utc::now(),
+                        })?
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                line.push(b'\n');
+                if let Err(err) = file.append(&line).await {
+                    warn!("failed to write audit log: {}", err);
+                }
+            }
+            _ = flush_ticker.tick() => {
+                if let Err(err) = file.writer.flush().await {
+                    warn!("failed to flush audit log: {}", err);
+                }
+            }
+            changed = shutdown_rx.changed() => {
+                if changed.is_ok() && *shutdown_rx.borrow() {
+                    break;
+                }
+            }
+        }
+    }
+
+    file.writer.flush().await?;
+    Ok(())
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".1");
+    PathBuf::from(name)
+}
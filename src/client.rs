@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::store::{Message, MessageSummary, StoreEvent, StoreEventType};
+
+#[derive(Debug, Clone)]
+pub struct ForsakenClient {
+    http: reqwest::Client,
+    base_url: String,
+    mailbox_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListResponse {
+    messages: Vec<MessageSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DetailResponse {
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteResponse {
+    deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClearResponse {
+    removed: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+impl ForsakenClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            mailbox_token: None,
+        }
+    }
+
+    pub fn with_mailbox_token(mut self, token: impl Into<String>) -> Self {
+        self.mailbox_token = Some(token.into());
+        self
+    }
+
+    pub async fn list(&self, mailbox: &str) -> anyhow::Result<Vec<MessageSummary>> {
+        let response: ListResponse = self
+            .send_json(self.request(Method::GET, mailbox, "messages"))
+            .await?;
+        Ok(response.messages)
+    }
+
+    pub async fn get(&self, mailbox: &str, id: &str) -> anyhow::Result<Option<Message>> {
+        let path = format!("messages/{}", id);
+        let response = self.request(Method::GET, mailbox, &path).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: DetailResponse = decode(response).await?;
+        Ok(Some(response.message))
+    }
+
+    pub async fn delete(&self, mailbox: &str, id: &str) -> anyhow::Result<bool> {
+        let path = format!("messages/{}", id);
+        let response = self.request(Method::DELETE, mailbox, &path).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        let response: DeleteResponse = decode(response).await?;
+        Ok(response.deleted)
+    }
+
+    pub async fn clear(&self, mailbox: &str) -> anyhow::Result<usize> {
+        let response: ClearResponse = self
+            .send_json(self.request(Method::DELETE, mailbox, "messages"))
+            .await?;
+        Ok(response.removed)
+    }
+
+    pub async fn wait_for_message(
+        &self,
+        mailbox: &str,
+        wait: Duration,
+    ) -> anyhow::Result<Option<Message>> {
+        let deadline = Instant::now() + wait;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+
+            let request = self
+                .request(Method::GET, mailbox, "events/next")
+                .timeout(remaining);
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(err) if err.is_timeout() => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if response.status() == StatusCode::NO_CONTENT {
+                continue;
+            }
+
+            let event: StoreEvent = decode(response).await?;
+            if !matches!(event.event, StoreEventType::Added) {
+                continue;
+            }
+            let Some(id) = event.message_id else {
+                continue;
+            };
+            if let Some(message) = self.get(mailbox, &id).await? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    fn request(&self, method: Method, mailbox: &str, path: &str) -> RequestBuilder {
+        let url = format!("{}/api/mailboxes/{}/{}", self.base_url, mailbox, path);
+        let request = self.http.request(method, url);
+        match &self.mailbox_token {
+            Some(token) => request.header("X-Mailbox-Token", token),
+            None => request,
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> anyhow::Result<T> {
+        decode(request.send().await?).await
+    }
+}
+
+async fn decode<T: DeserializeOwned>(response: Response) -> anyhow::Result<T> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = response
+            .json::<ErrorResponse>()
+            .await
+            .map(|body| body.error)
+            .unwrap_or_else(|_| status.to_string());
+        bail!("request failed with {}: {}", status.as_u16(), message);
+    }
+    response.json().await.context("invalid response body")
+}
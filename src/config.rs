@@ -1,6 +1,13 @@
 use std::collections::HashSet;
 use std::env;
 
+use chrono::{DateTime, FixedOffset, NaiveTime, Utc};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::address::{self, MailboxOptions};
+use crate::mail_parser::ParseOptions;
+
 const DEFAULT_MAILBOX_BLACKLIST: &[&str] = &[
     "admin",
     "master",
@@ -13,23 +20,143 @@ const DEFAULT_MAILBOX_BLACKLIST: &[&str] = &[
     "postmaster",
 ];
 
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub offset: FixedOffset,
+}
+
+impl AcceptWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.offset).time();
+        if self.start <= self.end {
+            local >= self.start && local < self.end
+        } else {
+            local >= self.start || local < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayMode {
+    CaptureAll,
+    RequireLocalDomain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxFullPolicy {
+    Evict,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizePolicy {
+    Truncate,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMinVersion {
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsCipherPolicy {
+    Compatible,
+    Modern,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonCase {
+    Snake,
+    Camel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogLevel {
+    Off,
+    Debug,
+    Info,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub http_addr: String,
     pub smtp_addr: String,
+    pub http_tls: bool,
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
+    pub tls_min_version: TlsMinVersion,
+    pub tls_cipher_policy: TlsCipherPolicy,
     pub domain: String,
+    pub display_domain: String,
     pub mailbox_blacklist: HashSet<String>,
     pub banned_sender_domains: HashSet<String>,
+    pub quarantine_mailbox: String,
     pub max_messages_per_mailbox: usize,
     pub message_ttl_minutes: i64,
     pub max_message_bytes: usize,
+    pub reverse_dns: bool,
+    pub require_from_header: bool,
+    pub ignore_local_dots: bool,
+    pub case_sensitive_mailbox: bool,
+    pub truncate_long_mailbox: bool,
+    pub spool_to_disk_bytes: usize,
+    pub sender_domain_rate_limit: usize,
+    pub sender_domain_rate_window_seconds: u64,
+    pub http_access_log: AccessLogLevel,
+    pub api_json_case: JsonCase,
+    pub greeting_delay_ms: u64,
+    pub smtp_idle_timeout_seconds: u64,
+    pub smtp_max_messages_per_connection: usize,
+    pub smtp_chunking: bool,
+    pub mailbox_tokens_required: bool,
+    pub mailbox_token_secret: String,
+    pub mailbox_token_mint_key: String,
+    pub debug_api: bool,
+    pub max_api_body_bytes: usize,
+    pub api_request_timeout_seconds: u64,
+    pub accept_window: Option<AcceptWindow>,
+    pub strip_incoming_received: bool,
+    pub relay_mode: RelayMode,
+    pub mailbox_full_policy: MailboxFullPolicy,
+    pub max_body_part_bytes: usize,
+    pub oversize_policy: OversizePolicy,
+    pub store_content_types: HashSet<String>,
+    pub audit_log_path: String,
+    pub audit_log_max_bytes: u64,
 }
 
 impl Config {
     pub fn load() -> Self {
         let http_addr = getenv_default("HTTP_ADDR", ":3000");
         let smtp_addr = getenv_default("SMTP_ADDR", ":25");
+        let http_tls = parse_bool_env("HTTP_TLS", false);
+        let tls_cert_path = getenv_default("TLS_CERT_PATH", "");
+        let tls_key_path = getenv_default("TLS_KEY_PATH", "");
+        let tls_min_version = match getenv_default("TLS_MIN_VERSION", "1.2").as_str() {
+            "1.2" => TlsMinVersion::Tls12,
+            "1.3" => TlsMinVersion::Tls13,
+            other => {
+                warn!("invalid TLS_MIN_VERSION: {}, using 1.2", other);
+                TlsMinVersion::Tls12
+            }
+        };
+        let tls_cipher_policy = match getenv_default("TLS_CIPHERS", "compatible")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "modern" => TlsCipherPolicy::Modern,
+            "compatible" => TlsCipherPolicy::Compatible,
+            other => {
+                warn!("invalid TLS_CIPHERS: {}, using compatible", other);
+                TlsCipherPolicy::Compatible
+            }
+        };
         let domain = normalize_domain(&env::var("MAIL_DOMAIN").unwrap_or_default());
+        let display_domain = normalize_domain(&env::var("DISPLAY_DOMAIN").unwrap_or_default());
 
         let mailbox_blacklist = parse_list_env("MAILBOX_BLACKLIST").unwrap_or_else(|| {
             DEFAULT_MAILBOX_BLACKLIST
@@ -38,20 +165,192 @@ impl Config {
                 .collect()
         });
         let banned_sender_domains = parse_list_env("BANNED_SENDER_DOMAINS").unwrap_or_default();
+        let quarantine_mailbox = getenv_default("QUARANTINE_MAILBOX", "");
 
         let max_messages_per_mailbox = parse_usize_env("MAX_MESSAGES_PER_MAILBOX", 200).max(1);
         let message_ttl_minutes = parse_i64_env("MESSAGE_TTL_MINUTES", 1440).max(1);
         let max_message_bytes = parse_usize_env("MAX_MESSAGE_BYTES", 10 * 1024 * 1024).max(1024);
+        let max_body_part_bytes = parse_usize_env("MAX_BODY_PART_BYTES", 1024 * 1024).max(1024);
+        let store_content_types = parse_list_env("STORE_CONTENT_TYPES")
+            .unwrap_or_else(|| ["text/plain", "text/html"].map(String::from).into());
+        let oversize_policy = match getenv_default("OVERSIZE_POLICY", "truncate")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "reject" => OversizePolicy::Reject,
+            "truncate" => OversizePolicy::Truncate,
+            other => {
+                warn!("invalid OVERSIZE_POLICY: {}, using truncate", other);
+                OversizePolicy::Truncate
+            }
+        };
+        let reverse_dns = parse_bool_env("REVERSE_DNS", false);
+        let require_from_header = parse_bool_env("REQUIRE_FROM_HEADER", false);
+        let ignore_local_dots = parse_bool_env("IGNORE_LOCAL_DOTS", false);
+        let case_sensitive_mailbox = parse_bool_env("CASE_SENSITIVE_MAILBOX", false);
+        let truncate_long_mailbox = parse_bool_env("TRUNCATE_LONG_MAILBOX", false);
+        let spool_to_disk_bytes = parse_usize_env("SPOOL_TO_DISK_BYTES", 0);
+        let sender_domain_rate_limit = parse_usize_env("SENDER_DOMAIN_RATE_LIMIT", 0);
+        let sender_domain_rate_window_seconds =
+            parse_usize_env("SENDER_DOMAIN_RATE_WINDOW_SECONDS", 60).max(1) as u64;
+        let greeting_delay_ms = parse_usize_env("GREETING_DELAY_MS", 0) as u64;
+        let smtp_idle_timeout_seconds = parse_usize_env("SMTP_IDLE_TIMEOUT_SECONDS", 300) as u64;
+        let smtp_max_messages_per_connection =
+            parse_usize_env("SMTP_MAX_MESSAGES_PER_CONNECTION", 0);
+        let smtp_chunking = parse_bool_env("SMTP_CHUNKING", false);
+        let mailbox_tokens_required = parse_bool_env("MAILBOX_TOKENS_REQUIRED", false);
+        let mut mailbox_token_secret = getenv_default("MAILBOX_TOKEN_SECRET", "");
+        if mailbox_tokens_required && mailbox_token_secret.is_empty() {
+            warn!("MAILBOX_TOKEN_SECRET is not set, tokens will not survive a restart");
+            mailbox_token_secret = Uuid::new_v4().simple().to_string();
+        }
+        let mailbox_token_mint_key = getenv_default("MAILBOX_TOKEN_MINT_KEY", "");
+        if mailbox_tokens_required && mailbox_token_mint_key.is_empty() {
+            warn!(
+                "MAILBOX_TOKENS_REQUIRED is set without MAILBOX_TOKEN_MINT_KEY: token minting, \
+                 message-id search and purging all mailboxes are disabled"
+            );
+        }
+        let debug_api = parse_bool_env("DEBUG_API", false);
+        let max_api_body_bytes = parse_usize_env("MAX_API_BODY_BYTES", 1024 * 1024).max(1024);
+        let api_request_timeout_seconds = parse_usize_env("API_REQUEST_TIMEOUT_SECONDS", 30) as u64;
+        let accept_window = parse_accept_window_env("ACCEPT_WINDOW", "ACCEPT_WINDOW_TZ");
+        let strip_incoming_received = parse_bool_env("STRIP_INCOMING_RECEIVED", false);
+        let relay_mode = match getenv_default("RELAY_MODE", "capture_all")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "require_local_domain" => RelayMode::RequireLocalDomain,
+            "capture_all" => RelayMode::CaptureAll,
+            other => {
+                warn!("invalid RELAY_MODE: {}, using capture_all", other);
+                RelayMode::CaptureAll
+            }
+        };
+        if relay_mode == RelayMode::RequireLocalDomain && domain.is_empty() {
+            warn!("RELAY_MODE=require_local_domain without MAIL_DOMAIN rejects every recipient");
+        }
+        let mailbox_full_policy = match getenv_default("MAILBOX_FULL_POLICY", "evict")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "reject" => MailboxFullPolicy::Reject,
+            "evict" => MailboxFullPolicy::Evict,
+            other => {
+                warn!("invalid MAILBOX_FULL_POLICY: {}, using evict", other);
+                MailboxFullPolicy::Evict
+            }
+        };
+        let audit_log_path = getenv_default("AUDIT_LOG_PATH", "");
+        let audit_log_max_bytes = parse_usize_env("AUDIT_LOG_MAX_BYTES", 100 * 1024 * 1024) as u64;
+        let http_access_log = match getenv_default("HTTP_ACCESS_LOG", "info")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "off" | "none" | "false" => AccessLogLevel::Off,
+            "debug" => AccessLogLevel::Debug,
+            "info" => AccessLogLevel::Info,
+            other => {
+                warn!("invalid HTTP_ACCESS_LOG: {}, using info", other);
+                AccessLogLevel::Info
+            }
+        };
+        let api_json_case = match getenv_default("API_JSON_CASE", "snake")
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "camel" => JsonCase::Camel,
+            "snake" => JsonCase::Snake,
+            other => {
+                warn!("invalid API_JSON_CASE: {}, using snake", other);
+                JsonCase::Snake
+            }
+        };
 
-        Self {
+        let mut cfg = Self {
             http_addr,
             smtp_addr,
+            http_tls,
+            tls_cert_path,
+            tls_key_path,
+            tls_min_version,
+            tls_cipher_policy,
             domain,
+            display_domain,
             mailbox_blacklist,
             banned_sender_domains,
+            quarantine_mailbox,
             max_messages_per_mailbox,
             message_ttl_minutes,
             max_message_bytes,
+            reverse_dns,
+            require_from_header,
+            ignore_local_dots,
+            case_sensitive_mailbox,
+            truncate_long_mailbox,
+            spool_to_disk_bytes,
+            sender_domain_rate_limit,
+            sender_domain_rate_window_seconds,
+            http_access_log,
+            api_json_case,
+            greeting_delay_ms,
+            smtp_idle_timeout_seconds,
+            smtp_max_messages_per_connection,
+            smtp_chunking,
+            mailbox_tokens_required,
+            mailbox_token_secret,
+            mailbox_token_mint_key,
+            debug_api,
+            max_api_body_bytes,
+            api_request_timeout_seconds,
+            accept_window,
+            strip_incoming_received,
+            relay_mode,
+            mailbox_full_policy,
+            max_body_part_bytes,
+            oversize_policy,
+            store_content_types,
+            audit_log_path,
+            audit_log_max_bytes,
+        };
+
+        // Store under the same key the API resolves the name to.
+        if !cfg.quarantine_mailbox.is_empty() {
+            match address::normalize_mailbox(&cfg.quarantine_mailbox, &cfg.mailbox_options()) {
+                Ok((mailbox, _)) => cfg.quarantine_mailbox = mailbox,
+                Err(err) => {
+                    warn!("invalid QUARANTINE_MAILBOX: {}, quarantine disabled", err);
+                    cfg.quarantine_mailbox.clear();
+                }
+            }
+        }
+        cfg
+    }
+
+    pub fn mailbox_options(&self) -> MailboxOptions<'_> {
+        MailboxOptions {
+            domain: &self.domain,
+            display_domain: &self.display_domain,
+            ignore_local_dots: self.ignore_local_dots,
+            case_sensitive: self.case_sensitive_mailbox,
+            truncate_long: self.truncate_long_mailbox,
+        }
+    }
+
+    pub fn parse_options(&self) -> ParseOptions<'_> {
+        ParseOptions {
+            max_body_part_bytes: self.max_body_part_bytes,
+            content_types: &self.store_content_types,
+        }
+    }
+
+    pub fn is_local_recipient(&self, address: &str) -> bool {
+        if self.domain.is_empty() {
+            return false;
+        }
+        match address.trim().rsplit_once('@') {
+            Some((_, domain)) => domain.trim().eq_ignore_ascii_case(&self.domain),
+            None => true,
         }
     }
 
@@ -94,6 +393,49 @@ fn parse_i64_env(key: &str, fallback: i64) -> i64 {
         .unwrap_or(fallback)
 }
 
+fn parse_bool_env(key: &str, fallback: bool) -> bool {
+    match env::var(key) {
+        Ok(v) => match v.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => fallback,
+        },
+        Err(_) => fallback,
+    }
+}
+
+fn parse_accept_window_env(key: &str, tz_key: &str) -> Option<AcceptWindow> {
+    let value = getenv_default(key, "");
+    if value.is_empty() {
+        return None;
+    }
+
+    let tz = getenv_default(tz_key, "+00:00");
+    let offset = if tz.eq_ignore_ascii_case("utc") || tz.eq_ignore_ascii_case("z") {
+        Some(FixedOffset::east_opt(0).expect("zero offset is valid"))
+    } else {
+        tz.parse::<FixedOffset>().ok()
+    };
+    let Some(offset) = offset else {
+        warn!("invalid {}: {}, accept window disabled", tz_key, tz);
+        return None;
+    };
+
+    let window = value.split_once('-').and_then(|(start, end)| {
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+        // An equal start and end would match no time and refuse all mail.
+        (start != end).then_some(AcceptWindow { start, end, offset })
+    });
+    if window.is_none() {
+        warn!(
+            "invalid {}: {}, expected HH:MM-HH:MM with distinct times, accept window disabled",
+            key, value
+        );
+    }
+    window
+}
+
 fn parse_list_env(key: &str) -> Option<HashSet<String>> {
     let value = env::var(key).ok()?;
     let mut out = HashSet::new();
@@ -1,27 +1,43 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::extract::{Path, Query, State};
-use axum::http::{header, StatusCode, Uri};
+use axum::body::{Body, Bytes};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use futures_util::stream;
 use include_dir::{include_dir, Dir};
 use serde::Deserialize;
 use serde::Serialize;
-use tokio::time::timeout;
+use serde_json::Value;
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::{debug, info};
 
 use crate::address;
-use crate::config::Config;
-use crate::store::{Message, MessageSummary, Store};
+use crate::config::{AccessLogLevel, Config, JsonCase};
+use crate::mail_parser::{self, ParsedMessage};
+use crate::mailbox_token;
+use crate::mbox;
+use crate::store::{
+    LabelError, Message, MessageSummary, Store, StoreEvent, MAX_LABELS_PER_MESSAGE,
+};
 
 static EMBEDDED_PUBLIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/public");
 
+const MAX_COALESCE_MS: u64 = 10_000;
+
 #[derive(Clone)]
 pub struct AppState {
     pub cfg: Arc<Config>,
     pub store: Store,
+    pub smtp_ok: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,12 +45,48 @@ struct EmailQuery {
     email: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    label: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LatestQuery {
+    offset: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelsRequest {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfirmQuery {
+    confirm: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    coalesce_ms: Option<u64>,
+}
+
 #[derive(Debug, Serialize)]
 struct HealthResponse {
     status: &'static str,
     version: &'static str,
 }
 
+#[derive(Debug, Serialize)]
+struct ReadyResponse {
+    status: &'static str,
+    smtp_ok: bool,
+}
+
 #[derive(Debug, Serialize)]
 struct ListResponse {
     mailbox: String,
@@ -50,11 +102,41 @@ struct DetailResponse {
     message: Message,
 }
 
+#[derive(Debug, Serialize)]
+struct LabelsResponse {
+    mailbox: String,
+    email: String,
+    id: String,
+    labels: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenResponse {
+    mailbox: String,
+    email: String,
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    mailbox: String,
+    message: Message,
+}
+
+#[derive(Debug, Serialize)]
+struct EventBatchResponse {
+    event: &'static str,
+    mailbox: String,
+    count: usize,
+    events: Vec<StoreEvent>,
+}
+
 #[derive(Debug, Serialize)]
 struct DeleteResponse {
     mailbox: String,
     email: String,
     deleted: bool,
+    remaining: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -64,27 +146,196 @@ struct ClearResponse {
     removed: usize,
 }
 
+#[derive(Debug, Serialize)]
+struct ClearAllResponse {
+    mailboxes: usize,
+    removed: usize,
+}
+
 pub fn router(state: AppState) -> Router {
-    Router::new()
+    let router = Router::new()
         .route("/api/health", get(health))
+        .route("/api/ready", get(ready))
         .route("/api/messages", get(list_by_email))
         .route("/api/messages/{id}", get(get_by_email))
+        .route("/api/mailboxes", delete(clear_all_mailboxes))
+        .route(
+            "/api/debug/parse",
+            post(debug_parse).layer(DefaultBodyLimit::max(state.cfg.max_message_bytes)),
+        )
+        .route(
+            "/api/search/message-id/{*message_id}",
+            get(find_by_message_id),
+        )
         .route(
             "/api/mailboxes/{mailbox}/messages",
-            get(list_by_mailbox).delete(clear_mailbox),
+            get(list_by_mailbox)
+                .head(count_by_mailbox)
+                .delete(clear_mailbox),
+        )
+        .route(
+            "/api/mailboxes/{mailbox}/messages/latest",
+            get(get_latest_by_mailbox),
         )
         .route(
             "/api/mailboxes/{mailbox}/messages/{id}",
             get(get_by_mailbox).delete(delete_by_mailbox),
         )
+        .route(
+            "/api/mailboxes/{mailbox}/messages/{id}/labels",
+            post(update_labels),
+        )
+        .route("/api/mailboxes/{mailbox}/token", post(mint_mailbox_token))
+        .route(
+            "/api/mailboxes/{mailbox}/export.jsonl",
+            get(export_mailbox_jsonl),
+        )
+        .route(
+            "/api/mailboxes/{mailbox}/export.mbox",
+            get(export_mailbox_mbox),
+        )
         .route(
             "/api/mailboxes/{mailbox}/events/next",
             get(next_mailbox_event),
         )
-        .fallback(get(serve_embedded_static))
+        .fallback(get(serve_embedded_static));
+    let router = match state.cfg.api_json_case {
+        JsonCase::Camel => router.layer(middleware::from_fn(camel_case_json)),
+        JsonCase::Snake => router,
+    };
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_api_writes,
+        ))
+        .layer(DefaultBodyLimit::max(state.cfg.max_api_body_bytes))
+        .layer(middleware::from_fn_with_state(state.clone(), access_log))
         .with_state(state)
 }
 
+async fn limit_api_writes(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if request.method() != Method::POST || !request.uri().path().starts_with("/api/") {
+        return next.run(request).await;
+    }
+
+    // The debug parser takes whole messages, so it shares the SMTP size limit.
+    let limit = if request.uri().path() == "/api/debug/parse" {
+        state.cfg.max_message_bytes
+    } else {
+        state.cfg.max_api_body_bytes
+    };
+    let declared = header_value(request.headers(), header::CONTENT_LENGTH.as_str())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return ApiError::payload_too_large(limit).into_response();
+    }
+
+    let response = match state.cfg.api_request_timeout_seconds {
+        0 => next.run(request).await,
+        seconds => match timeout(Duration::from_secs(seconds), next.run(request)).await {
+            Ok(response) => response,
+            Err(_) => return ApiError::service_unavailable("request timed out").into_response(),
+        },
+    };
+    // Body extractors reject chunked uploads past the limit with a plain-text 413.
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return ApiError::payload_too_large(limit).into_response();
+    }
+    response
+}
+
+async fn access_log(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let level = state.cfg.http_access_log;
+    if level == AccessLogLevel::Off {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+
+    if level == AccessLogLevel::Info && path.starts_with("/api/") {
+        info!(
+            "http {} {} status={} latency_ms={:.1} client={}",
+            method, path, status, latency_ms, client
+        );
+    } else {
+        debug!(
+            "http {} {} status={} latency_ms={:.1} client={}",
+            method, path, status, latency_ms, client
+        );
+    }
+
+    response
+}
+
+async fn camel_case_json(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    camel_case_keys(&mut value);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(value.to_string()))
+}
+
+fn camel_case_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut item)| {
+                    // Header names are data, not field names.
+                    if key != "headers" {
+                        camel_case_keys(&mut item);
+                    }
+                    (to_camel_case(&key), item)
+                })
+                .collect();
+        }
+        Value::Array(items) => items.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for ch in key.chars() {
+        if ch == '_' {
+            upper = !out.is_empty();
+        } else if upper {
+            out.push(ch.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
 async fn serve_embedded_static(uri: Uri) -> Response {
     let normalized_path = normalize_static_path(uri.path());
     let candidates = if normalized_path.is_empty() {
@@ -146,9 +397,21 @@ async fn health() -> Json<HealthResponse> {
     })
 }
 
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadyResponse>) {
+    let smtp_ok = state.smtp_ok.load(Ordering::Relaxed);
+    let (status_code, status) = if smtp_ok {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (status_code, Json(ReadyResponse { status, smtp_ok }))
+}
+
 async fn list_by_email(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(query): Query<EmailQuery>,
+    Query(filter): Query<ListQuery>,
 ) -> Result<Json<ListResponse>, ApiError> {
     let email_input = query
         .email
@@ -157,14 +420,15 @@ async fn list_by_email(
         .filter(|v| !v.is_empty())
         .ok_or_else(|| ApiError::bad_request("missing email query parameter"))?;
 
-    write_message_list(&state, email_input).await
+    write_message_list(&state, &headers, email_input, &filter).await
 }
 
 async fn get_by_email(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<String>,
     Query(query): Query<EmailQuery>,
-) -> Result<Json<DetailResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     let email_input = query
         .email
         .as_deref()
@@ -172,49 +436,90 @@ async fn get_by_email(
         .filter(|v| !v.is_empty())
         .ok_or_else(|| ApiError::bad_request("missing email query parameter"))?;
 
-    write_message_detail(&state, email_input, &id).await
+    write_message_detail(&state, &headers, email_input, &id).await
 }
 
 async fn list_by_mailbox(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(mailbox): Path<String>,
+    Query(filter): Query<ListQuery>,
 ) -> Result<Json<ListResponse>, ApiError> {
-    write_message_list(&state, &mailbox).await
+    write_message_list(&state, &headers, &mailbox, &filter).await
+}
+
+async fn count_by_mailbox(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mailbox): Path<String>,
+) -> Result<Response, ApiError> {
+    let (mailbox, _) = resolve_mailbox(&state, &headers, &mailbox)?;
+
+    let count = state.store.count(&mailbox).await;
+    if count == 0 {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    Ok((StatusCode::OK, [("x-message-count", count.to_string())]).into_response())
 }
 
 async fn get_by_mailbox(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path((mailbox, id)): Path<(String, String)>,
-) -> Result<Json<DetailResponse>, ApiError> {
-    write_message_detail(&state, &mailbox, &id).await
+) -> Result<Response, ApiError> {
+    write_message_detail(&state, &headers, &mailbox, &id).await
+}
+
+async fn get_latest_by_mailbox(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mailbox): Path<String>,
+    Query(query): Query<LatestQuery>,
+) -> Result<Response, ApiError> {
+    let (mailbox, email) = resolve_mailbox(&state, &headers, &mailbox)?;
+
+    let message = state
+        .store
+        .latest(&mailbox, query.offset.unwrap_or(0))
+        .await
+        .ok_or_else(|| ApiError::not_found("message not found"))?;
+
+    Ok(detail_response(mailbox, email, message))
 }
 
 async fn delete_by_mailbox(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path((mailbox, id)): Path<(String, String)>,
 ) -> Result<Json<DeleteResponse>, ApiError> {
-    let (mailbox, email) =
-        address::normalize_mailbox(&mailbox, &state.cfg.domain).map_err(ApiError::bad_request)?;
+    let (mailbox, email) = resolve_mailbox(&state, &headers, &mailbox)?;
 
     let message_id = id.trim();
     if message_id.is_empty() {
         return Err(ApiError::bad_request("missing message id"));
     }
 
-    let deleted = state.store.delete(&mailbox, message_id).await;
+    let if_match = header_value(&headers, "if-match");
+    let (deleted, remaining) = state.store.delete(&mailbox, message_id, if_match).await;
+    if if_match.is_some() && !deleted {
+        return Err(ApiError::precondition_failed(
+            "message does not match If-Match",
+        ));
+    }
     Ok(Json(DeleteResponse {
         mailbox,
         email,
         deleted,
+        remaining,
     }))
 }
 
 async fn clear_mailbox(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(mailbox): Path<String>,
 ) -> Result<Json<ClearResponse>, ApiError> {
-    let (mailbox, email) =
-        address::normalize_mailbox(&mailbox, &state.cfg.domain).map_err(ApiError::bad_request)?;
+    let (mailbox, email) = resolve_mailbox(&state, &headers, &mailbox)?;
     let removed = state.store.clear(&mailbox).await;
 
     Ok(Json(ClearResponse {
@@ -224,19 +529,195 @@ async fn clear_mailbox(
     }))
 }
 
+async fn export_mailbox_jsonl(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mailbox): Path<String>,
+) -> Result<Response, ApiError> {
+    let (mailbox, _) = resolve_mailbox(&state, &headers, &mailbox)?;
+
+    let messages = state.store.list(&mailbox).await;
+    let camel = state.cfg.api_json_case == JsonCase::Camel;
+    // The camel_case_json layer only rewrites whole JSON bodies, so each
+    // NDJSON line is converted here.
+    let lines = stream::iter(messages.into_iter().map(move |message| {
+        let mut value = serde_json::to_value(&message)?;
+        if camel {
+            camel_case_keys(&mut value);
+        }
+        serde_json::to_vec(&value).map(|mut line| {
+            line.push(b'\n');
+            Bytes::from(line)
+        })
+    }));
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+async fn export_mailbox_mbox(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mailbox): Path<String>,
+) -> Result<Response, ApiError> {
+    let (mailbox, _) = resolve_mailbox(&state, &headers, &mailbox)?;
+
+    let mut messages = state.store.list(&mailbox).await;
+    messages.reverse();
+    let entries = stream::iter(
+        messages
+            .into_iter()
+            .map(|message| Ok::<_, std::convert::Infallible>(Bytes::from(mbox::render(&message)))),
+    );
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/mbox")],
+        Body::from_stream(entries),
+    )
+        .into_response())
+}
+
+async fn update_labels(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((mailbox, id)): Path<(String, String)>,
+    Json(request): Json<LabelsRequest>,
+) -> Result<Json<LabelsResponse>, ApiError> {
+    let (mailbox, email) = resolve_mailbox(&state, &headers, &mailbox)?;
+
+    let message_id = id.trim();
+    if message_id.is_empty() {
+        return Err(ApiError::bad_request("missing message id"));
+    }
+
+    let add = normalize_labels(&request.add)?;
+    let remove = normalize_labels(&request.remove)?;
+    let labels = state
+        .store
+        .update_labels(&mailbox, message_id, &add, &remove)
+        .await
+        .map_err(|err| match err {
+            LabelError::NotFound => ApiError::not_found("message not found"),
+            LabelError::TooMany => ApiError::bad_request(format!(
+                "a message can have at most {} labels",
+                MAX_LABELS_PER_MESSAGE
+            )),
+        })?;
+
+    Ok(Json(LabelsResponse {
+        mailbox,
+        email,
+        id: message_id.to_string(),
+        labels,
+    }))
+}
+
+async fn mint_mailbox_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mailbox): Path<String>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    if state.cfg.mailbox_token_secret.is_empty() {
+        return Err(ApiError::not_found("mailbox tokens are not enabled"));
+    }
+    check_mint_key(&state, &headers)?;
+
+    let (mailbox, email) = address::normalize_mailbox(&mailbox, &state.cfg.mailbox_options())
+        .map_err(ApiError::bad_request)?;
+    let token = mailbox_token::mint(&state.cfg.mailbox_token_secret, &mailbox);
+
+    Ok(Json(TokenResponse {
+        mailbox,
+        email,
+        token,
+    }))
+}
+
+async fn debug_parse(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<ParsedMessage>, ApiError> {
+    if !state.cfg.debug_api {
+        return Err(ApiError::not_found("debug api is not enabled"));
+    }
+    let content_type = header_value(&headers, header::CONTENT_TYPE.as_str())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default();
+    if !content_type.eq_ignore_ascii_case("message/rfc822") {
+        return Err(ApiError::unsupported_media_type(
+            "Content-Type must be message/rfc822",
+        ));
+    }
+    if body.is_empty() {
+        return Err(ApiError::bad_request("missing message body"));
+    }
+
+    let parsed =
+        mail_parser::parse(&body, &state.cfg.parse_options()).map_err(ApiError::bad_request)?;
+    Ok(Json(parsed))
+}
+
+async fn find_by_message_id(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(message_id): Path<String>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    if state.cfg.mailbox_tokens_required {
+        check_mint_key(&state, &headers)?;
+    }
+    if message_id.trim().is_empty() {
+        return Err(ApiError::bad_request("missing message id"));
+    }
+
+    let (mailbox, message) = state
+        .store
+        .find_by_message_id(&message_id)
+        .await
+        .ok_or_else(|| ApiError::not_found("message not found"))?;
+
+    Ok(Json(SearchResponse { mailbox, message }))
+}
+
+async fn clear_all_mailboxes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ConfirmQuery>,
+) -> Result<Json<ClearAllResponse>, ApiError> {
+    if state.cfg.mailbox_tokens_required {
+        check_mint_key(&state, &headers)?;
+    }
+    if query.confirm != Some(true) {
+        return Err(ApiError::bad_request(
+            "purging all mailboxes requires confirm=true",
+        ));
+    }
+
+    let (removed, mailboxes) = state.store.clear_all().await;
+    Ok(Json(ClearAllResponse { mailboxes, removed }))
+}
+
 async fn next_mailbox_event(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(mailbox): Path<String>,
+    Query(query): Query<EventsQuery>,
 ) -> Result<Response, ApiError> {
-    let (mailbox, _) =
-        address::normalize_mailbox(&mailbox, &state.cfg.domain).map_err(ApiError::bad_request)?;
+    let (mailbox, _) = resolve_mailbox(&state, &headers, &mailbox)?;
+    let coalesce_ms = query.coalesce_ms.unwrap_or(0).min(MAX_COALESCE_MS);
 
     let mut receiver = state.store.subscribe();
-    loop {
+    let first = loop {
         match timeout(Duration::from_secs(25), receiver.recv()).await {
             Ok(Ok(event)) => {
                 if event.mailbox == mailbox {
-                    return Ok((StatusCode::OK, Json(event)).into_response());
+                    break event;
                 }
             }
             Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(_))) => continue,
@@ -245,19 +726,62 @@ async fn next_mailbox_event(
             }
             Err(_) => return Ok(StatusCode::NO_CONTENT.into_response()),
         }
+    };
+
+    if coalesce_ms == 0 {
+        return Ok((StatusCode::OK, Json(first)).into_response());
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(coalesce_ms);
+    let mut events = vec![first];
+    loop {
+        match timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(event)) => {
+                if event.mailbox == mailbox {
+                    events.push(event);
+                }
+            }
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Closed)) | Err(_) => break,
+        }
     }
+
+    Ok((
+        StatusCode::OK,
+        Json(EventBatchResponse {
+            event: "batch",
+            mailbox,
+            count: events.len(),
+            events,
+        }),
+    )
+        .into_response())
 }
 
 async fn write_message_list(
     state: &AppState,
+    headers: &HeaderMap,
     mailbox_input: &str,
+    filter: &ListQuery,
 ) -> Result<Json<ListResponse>, ApiError> {
-    let (mailbox, email) = address::normalize_mailbox(mailbox_input, &state.cfg.domain)
-        .map_err(ApiError::bad_request)?;
+    let (mailbox, email) = resolve_mailbox(state, headers, mailbox_input)?;
+    let label = match filter.label.as_deref() {
+        Some(value) => Some(normalize_label(value)?),
+        None => None,
+    };
+    let since = parse_timestamp_param("since", filter.since.as_deref())?;
+    let until = parse_timestamp_param("until", filter.until.as_deref())?;
 
     let messages = state.store.list(&mailbox).await;
     let summaries = messages
         .iter()
+        .filter(|item| {
+            label
+                .as_ref()
+                .is_none_or(|label| item.labels.contains(label))
+        })
+        .filter(|item| since.is_none_or(|since| item.received_at > since))
+        .filter(|item| until.is_none_or(|until| item.received_at <= until))
         .map(|item| item.summary())
         .collect::<Vec<_>>();
 
@@ -271,11 +795,11 @@ async fn write_message_list(
 
 async fn write_message_detail(
     state: &AppState,
+    headers: &HeaderMap,
     mailbox_input: &str,
     message_id: &str,
-) -> Result<Json<DetailResponse>, ApiError> {
-    let (mailbox, email) = address::normalize_mailbox(mailbox_input, &state.cfg.domain)
-        .map_err(ApiError::bad_request)?;
+) -> Result<Response, ApiError> {
+    let (mailbox, email) = resolve_mailbox(state, headers, mailbox_input)?;
 
     let message_id = message_id.trim();
     if message_id.is_empty() {
@@ -288,11 +812,99 @@ async fn write_message_detail(
         .await
         .ok_or_else(|| ApiError::not_found("message not found"))?;
 
-    Ok(Json(DetailResponse {
-        mailbox,
-        email,
-        message,
-    }))
+    Ok(detail_response(mailbox, email, message))
+}
+
+fn detail_response(mailbox: String, email: String, message: Message) -> Response {
+    let etag = message.etag();
+    (
+        [(header::ETAG, etag)],
+        Json(DetailResponse {
+            mailbox,
+            email,
+            message,
+        }),
+    )
+        .into_response()
+}
+
+fn resolve_mailbox(
+    state: &AppState,
+    headers: &HeaderMap,
+    mailbox_input: &str,
+) -> Result<(String, String), ApiError> {
+    let (mailbox, email) = address::normalize_mailbox(mailbox_input, &state.cfg.mailbox_options())
+        .map_err(ApiError::bad_request)?;
+
+    if state.cfg.mailbox_tokens_required {
+        let token = header_value(headers, "x-mailbox-token")
+            .ok_or_else(|| ApiError::unauthorized("missing mailbox token"))?;
+        if !mailbox_token::verify(&state.cfg.mailbox_token_secret, &mailbox, token) {
+            return Err(ApiError::unauthorized("invalid mailbox token"));
+        }
+    }
+
+    Ok((mailbox, email))
+}
+
+// The mint key doubles as the operator credential for routes that span
+// every mailbox, since no single mailbox token can authorize them.
+fn check_mint_key(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let mint_key = state.cfg.mailbox_token_mint_key.as_str();
+    if mint_key.is_empty() {
+        if state.cfg.mailbox_tokens_required {
+            return Err(ApiError::forbidden(
+                "MAILBOX_TOKEN_MINT_KEY is required while mailbox tokens are required",
+            ));
+        }
+        return Ok(());
+    }
+    let provided = header_value(headers, "x-mint-key").unwrap_or_default();
+    if !mailbox_token::keys_match(mint_key, provided) {
+        return Err(ApiError::unauthorized("invalid mint key"));
+    }
+    Ok(())
+}
+
+fn normalize_labels(values: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut out = Vec::new();
+    for value in values {
+        let label = normalize_label(value)?;
+        if !out.contains(&label) {
+            out.push(label);
+        }
+    }
+    Ok(out)
+}
+
+fn normalize_label(value: &str) -> Result<String, ApiError> {
+    let label = value.trim().to_lowercase();
+    if label.is_empty() || label.chars().count() > 64 || label.chars().any(char::is_control) {
+        return Err(ApiError::bad_request(format!("invalid label: {}", value)));
+    }
+    Ok(label)
+}
+
+fn parse_timestamp_param(
+    name: &str,
+    value: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, ApiError> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+        .map_err(|_| {
+            ApiError::bad_request(format!("invalid {}: expected RFC 3339 timestamp", name))
+        })
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
 }
 
 #[derive(Debug)]
@@ -309,6 +921,20 @@ impl ApiError {
         }
     }
 
+    fn unauthorized(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            message: message.into(),
+        }
+    }
+
+    fn forbidden(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message: message.into(),
+        }
+    }
+
     fn not_found(message: impl Into<String>) -> Self {
         Self {
             status: StatusCode::NOT_FOUND,
@@ -316,6 +942,27 @@ impl ApiError {
         }
     }
 
+    fn precondition_failed(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::PRECONDITION_FAILED,
+            message: message.into(),
+        }
+    }
+
+    fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            message: message.into(),
+        }
+    }
+
+    fn payload_too_large(limit: usize) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            message: format!("request body exceeds {} bytes", limit),
+        }
+    }
+
     fn service_unavailable(message: impl Into<String>) -> Self {
         Self {
             status: StatusCode::SERVICE_UNAVAILABLE,
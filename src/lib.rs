@@ -0,0 +1,3 @@
+#[cfg(feature = "client")]
+pub mod client;
+pub mod store;
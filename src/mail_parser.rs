@@ -1,69 +1,137 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use charset::Charset;
 use chrono::{DateTime, Utc};
 use mailparse::{self, ParsedMail};
+use serde::Serialize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions<'a> {
+    pub max_body_part_bytes: usize,
+    pub content_types: &'a HashSet<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ParsedMessage {
     pub from: String,
+    pub to: String,
     pub subject: String,
     pub date: DateTime<Utc>,
+    pub date_raw: Option<String>,
+    pub date_parse_failed: bool,
     pub text: Option<String>,
     pub html: Option<String>,
     pub headers: HashMap<String, Vec<String>>,
+    pub truncated: bool,
+    pub warnings: Vec<String>,
 }
 
-pub fn parse(raw: &[u8]) -> Result<ParsedMessage, String> {
+pub fn parse(raw: &[u8], options: &ParseOptions<'_>) -> Result<ParsedMessage, String> {
     let parsed =
         mailparse::parse_mail(raw).map_err(|e| format!("failed to parse raw message: {e}"))?;
     let headers = extract_headers(&parsed);
     let from = find_first_header(&headers, "From").unwrap_or_default();
+    let to = find_first_header(&headers, "To").unwrap_or_default();
     let subject = find_first_header(&headers, "Subject").unwrap_or_default();
-    let date = parse_date(find_first_header(&headers, "Date").as_deref());
+    let date_raw = find_first_header(&headers, "Date");
+    let (date, date_parse_failed) = parse_date(date_raw.as_deref());
 
     let mut text_parts = Vec::new();
     let mut html_parts = Vec::new();
-    collect_body_parts(&parsed, &mut text_parts, &mut html_parts);
+    let mut warnings = Vec::new();
+    if date_parse_failed {
+        warnings.push("unparseable Date header, using receive time".to_string());
+    }
+    collect_body_parts(
+        &parsed,
+        options,
+        &mut text_parts,
+        &mut html_parts,
+        &mut warnings,
+    );
+
+    let mut truncated = false;
+    for part in text_parts.iter_mut().chain(html_parts.iter_mut()) {
+        if truncate_part(part, options.max_body_part_bytes) {
+            truncated = true;
+            warnings.push(format!(
+                "body part truncated to {} bytes",
+                options.max_body_part_bytes
+            ));
+        }
+    }
 
     let text = join_parts(text_parts);
     let html = join_parts(html_parts);
 
     Ok(ParsedMessage {
-        from: from.trim().to_string(),
-        subject: subject.trim().to_string(),
+        from: single_line(&from),
+        to: single_line(&to),
+        subject: single_line(&subject),
         date,
+        date_raw,
+        date_parse_failed,
         text,
         html,
         headers,
+        truncated,
+        warnings,
     })
 }
 
 fn collect_body_parts(
     part: &ParsedMail<'_>,
+    options: &ParseOptions<'_>,
     text_parts: &mut Vec<String>,
     html_parts: &mut Vec<String>,
+    warnings: &mut Vec<String>,
 ) {
+    let content_type = part.ctype.mimetype.to_ascii_lowercase();
     if part.subparts.is_empty() {
-        let content_type = part.ctype.mimetype.to_ascii_lowercase();
-        if content_type == "text/plain" {
-            if let Ok(body) = part.get_body() {
-                if !body.trim().is_empty() {
-                    text_parts.push(body);
-                }
-            }
-        } else if content_type == "text/html" {
-            if let Ok(body) = part.get_body() {
+        if content_type.starts_with("multipart/") && !part.ctype.params.contains_key("boundary") {
+            warnings.push(format!("{} part without boundary", content_type));
+        }
+        let target = match content_type.as_str() {
+            "text/plain" => text_parts,
+            "text/html" => html_parts,
+            _ => return,
+        };
+        if !options.content_types.contains(&content_type) {
+            warnings.push(format!("dropped {} part", content_type));
+            return;
+        }
+        if Charset::for_label(part.ctype.charset.as_bytes()).is_none() {
+            warnings.push(format!(
+                "unknown charset {} in {} part",
+                part.ctype.charset, content_type
+            ));
+        }
+        match part.get_body() {
+            Ok(body) => {
                 if !body.trim().is_empty() {
-                    html_parts.push(body);
+                    target.push(body);
                 }
             }
+            Err(err) => warnings.push(format!("failed to decode {} part: {}", content_type, err)),
         }
         return;
     }
 
     for subpart in &part.subparts {
-        collect_body_parts(subpart, text_parts, html_parts);
+        collect_body_parts(subpart, options, text_parts, html_parts, warnings);
+    }
+}
+
+fn truncate_part(part: &mut String, max_bytes: usize) -> bool {
+    if part.len() <= max_bytes {
+        return false;
+    }
+    let mut end = max_bytes;
+    while !part.is_char_boundary(end) {
+        end -= 1;
     }
+    part.truncate(end);
+    true
 }
 
 fn extract_headers(part: &ParsedMail<'_>) -> HashMap<String, Vec<String>> {
@@ -76,6 +144,23 @@ fn extract_headers(part: &ParsedMail<'_>) -> HashMap<String, Vec<String>> {
     out
 }
 
+fn single_line(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut pending_space = false;
+    for ch in value.chars() {
+        if ch.is_whitespace() {
+            pending_space = true;
+        } else if !ch.is_control() {
+            if pending_space && !out.is_empty() {
+                out.push(' ');
+            }
+            pending_space = false;
+            out.push(ch);
+        }
+    }
+    out
+}
+
 fn find_first_header(headers: &HashMap<String, Vec<String>>, key: &str) -> Option<String> {
     headers.iter().find_map(|(header_key, values)| {
         if header_key.eq_ignore_ascii_case(key) {
@@ -86,15 +171,20 @@ fn find_first_header(headers: &HashMap<String, Vec<String>>, key: &str) -> Optio
     })
 }
 
-fn parse_date(date_header: Option<&str>) -> DateTime<Utc> {
+fn parse_date(date_header: Option<&str>) -> (DateTime<Utc>, bool) {
     let now = Utc::now();
     let Some(date_str) = date_header else {
-        return now;
+        return (now, false);
     };
 
-    match mailparse::dateparse(date_str) {
-        Ok(timestamp) => DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap_or(now),
-        Err(_) => now,
+    // dateparse returns Ok(0) when it never finds a day/month/year.
+    match mailparse::dateparse(date_str)
+        .ok()
+        .filter(|timestamp| *timestamp != 0)
+        .and_then(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+    {
+        Some(date) => (date, false),
+        None => (now, true),
     }
 }
 
@@ -0,0 +1,48 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn mint(secret: &str, mailbox: &str) -> String {
+    let mut mac = new_mac(secret);
+    mac.update(mailbox.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn verify(secret: &str, mailbox: &str, token: &str) -> bool {
+    let Some(expected) = decode_hex(token.trim()) else {
+        return false;
+    };
+    let mut mac = new_mac(secret);
+    mac.update(mailbox.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+// Constant-time equality for shared keys: both values are MACed under the
+// expected key so timing leaks neither the matching prefix nor the length.
+pub fn keys_match(expected: &str, provided: &str) -> bool {
+    let mut mac = new_mac(expected);
+    mac.update(expected.as_bytes());
+    let expected_tag = mac.finalize().into_bytes();
+    let mut mac = new_mac(expected);
+    mac.update(provided.as_bytes());
+    mac.verify_slice(&expected_tag).is_ok()
+}
+
+fn new_mac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length")
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
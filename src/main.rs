@@ -1,21 +1,33 @@
 mod address;
+mod audit_log;
 mod config;
 mod http_api;
 mod mail_parser;
+mod mailbox_token;
+mod mbox;
+mod rate_limit;
+mod reverse_dns;
 mod smtp_server;
-mod store;
+mod tls;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
+use axum::serve::ListenerExt;
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tracing::{error, info, warn};
 
+use forsaken_mail_rust::store;
+
 use crate::config::Config;
 use crate::http_api::AppState;
 use crate::store::Store;
+use crate::tls::TlsListener;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -23,20 +35,50 @@ async fn main() -> anyhow::Result<()> {
     info!("forsaken-mail-rust v{}", env!("CARGO_PKG_VERSION"));
 
     let cfg = Arc::new(Config::load());
-    let store = Store::new(cfg.max_messages_per_mailbox, cfg.message_ttl_minutes);
+    let store = Store::new(
+        cfg.max_messages_per_mailbox,
+        cfg.message_ttl_minutes,
+        cfg.case_sensitive_mailbox,
+    );
     info!("serving embedded static assets");
+    let tls_config = if cfg.http_tls {
+        Some(tls::load_server_config(&cfg)?)
+    } else {
+        None
+    };
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
+    let smtp_ok = Arc::new(AtomicBool::new(false));
     let smtp_cfg = cfg.clone();
     let smtp_store = store.clone();
+    let smtp_status = smtp_ok.clone();
     let smtp_shutdown = shutdown_rx.clone();
     let smtp_task = tokio::spawn(async move {
-        if let Err(err) = smtp_server::run(smtp_cfg, smtp_store, smtp_shutdown).await {
+        let result =
+            smtp_server::run(smtp_cfg, smtp_store, smtp_status.clone(), smtp_shutdown).await;
+        smtp_status.store(false, Ordering::Relaxed);
+        if let Err(err) = result {
             error!("SMTP server stopped with error: {}", err);
         }
     });
 
+    let audit_task = if cfg.audit_log_path.is_empty() {
+        None
+    } else {
+        let audit_path = PathBuf::from(&cfg.audit_log_path);
+        let audit_max_bytes = cfg.audit_log_max_bytes;
+        let audit_events = store.subscribe();
+        let audit_shutdown = shutdown_rx.clone();
+        Some(tokio::spawn(async move {
+            if let Err(err) =
+                audit_log::run(audit_path, audit_max_bytes, audit_events, audit_shutdown).await
+            {
+                error!("audit log stopped with error: {}", err);
+            }
+        }))
+    };
+
     let cleanup_store = store.clone();
     let mut cleanup_shutdown = shutdown_rx.clone();
     let cleanup_task = tokio::spawn(async move {
@@ -61,6 +103,7 @@ async fn main() -> anyhow::Result<()> {
     let app_state = AppState {
         cfg: cfg.clone(),
         store,
+        smtp_ok,
     };
     let router = http_api::router(app_state);
 
@@ -68,15 +111,41 @@ async fn main() -> anyhow::Result<()> {
     let listener = TcpListener::bind(&http_addr)
         .await
         .with_context(|| format!("failed to bind HTTP listener on {http_addr}"))?;
-    info!("HTTP listening on {}", http_addr);
+    let scheme = if tls_config.is_some() {
+        "HTTPS"
+    } else {
+        "HTTP"
+    };
+    info!("{} listening on {}", scheme, http_addr);
 
     let mut http_shutdown = shutdown_rx.clone();
     let http_task = tokio::spawn(async move {
-        let server = axum::serve(listener, router).with_graceful_shutdown(async move {
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        let shutdown = async move {
             let _ = http_shutdown.changed().await;
-        });
+        };
+        let result = match tls_config {
+            Some(tls_config) => {
+                let listener = match TlsListener::new(listener, tls_config) {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        error!("HTTPS listener failed: {}", err);
+                        return;
+                    }
+                };
+                // tap_io gives the TLS listener axum's ConnectInfo<SocketAddr> support.
+                axum::serve(listener.tap_io(|_| {}), service)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+            None => {
+                axum::serve(listener, service)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+        };
 
-        if let Err(err) = server.await {
+        if let Err(err) = result {
             error!("HTTP server stopped with error: {}", err);
         }
     });
@@ -94,6 +163,9 @@ async fn main() -> anyhow::Result<()> {
         let _ = http_task.await;
         let _ = smtp_task.await;
         let _ = cleanup_task.await;
+        if let Some(audit_task) = audit_task {
+            let _ = audit_task.await;
+        }
     })
     .await;
 
@@ -0,0 +1,88 @@
+use crate::store::Message;
+
+const REPLACED_HEADERS: [&str; 3] = ["content-type", "content-transfer-encoding", "mime-version"];
+
+pub fn render(message: &Message) -> Vec<u8> {
+    let mut out = format!(
+        "From {} {}\n",
+        envelope_sender(&message.envelope_from),
+        message.received_at.format("%a %b %e %H:%M:%S %Y")
+    );
+
+    let mut keys: Vec<&String> = message
+        .headers
+        .keys()
+        .filter(|key| !REPLACED_HEADERS.contains(&key.to_ascii_lowercase().as_str()))
+        .collect();
+    keys.sort_by_key(|key| {
+        (
+            !key.eq_ignore_ascii_case("Received"),
+            key.to_ascii_lowercase(),
+        )
+    });
+    for key in keys {
+        for value in &message.headers[key] {
+            out.push_str(&format!(
+                "{}: {}\n",
+                key,
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            ));
+        }
+    }
+
+    out.push_str("MIME-Version: 1.0\n");
+    match (message.text.as_deref(), message.html.as_deref()) {
+        (Some(text), Some(html)) => {
+            let boundary = format!("mbox-{}", message.id);
+            out.push_str(&format!(
+                "Content-Type: multipart/alternative; boundary=\"{}\"\n\n",
+                boundary
+            ));
+            for (content_type, body) in [("text/plain", text), ("text/html", html)] {
+                out.push_str(&format!("--{}\n", boundary));
+                push_part_headers(&mut out, content_type);
+                push_body(&mut out, body);
+            }
+            out.push_str(&format!("--{}--\n", boundary));
+        }
+        (Some(text), None) => {
+            push_part_headers(&mut out, "text/plain");
+            push_body(&mut out, text);
+        }
+        (None, Some(html)) => {
+            push_part_headers(&mut out, "text/html");
+            push_body(&mut out, html);
+        }
+        (None, None) => {
+            push_part_headers(&mut out, "text/plain");
+        }
+    }
+    out.push('\n');
+    out.into_bytes()
+}
+
+fn push_part_headers(out: &mut String, content_type: &str) {
+    out.push_str(&format!(
+        "Content-Type: {}; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n",
+        content_type
+    ));
+}
+
+fn push_body(out: &mut String, body: &str) {
+    for line in body.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            out.push('>');
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn envelope_sender(envelope_from: &str) -> &str {
+    let address = envelope_from.trim();
+    if address.is_empty() || address.contains(char::is_whitespace) {
+        "MAILER-DAEMON"
+    } else {
+        address
+    }
+}
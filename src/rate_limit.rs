@@ -0,0 +1,55 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct SlidingWindowLimiter {
+    limit: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl SlidingWindowLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: &str) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let Ok(mut hits) = self.hits.lock() else {
+            return true;
+        };
+        let entries = hits.entry(key.to_string()).or_default();
+        while entries
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= self.window)
+        {
+            entries.pop_front();
+        }
+
+        if entries.len() >= self.limit {
+            return false;
+        }
+        entries.push_back(now);
+        true
+    }
+
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let Ok(mut hits) = self.hits.lock() else {
+            return;
+        };
+        hits.retain(|_, entries| {
+            entries
+                .back()
+                .is_some_and(|at| now.duration_since(*at) < self.window)
+        });
+    }
+}
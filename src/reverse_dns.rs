@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use tokio::time::timeout;
+use tracing::debug;
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const CACHE_TTL: Duration = Duration::from_secs(300);
+const CACHE_MAX_ENTRIES: usize = 4096;
+
+type CacheEntry = (Option<String>, Instant);
+
+static CACHE: Lazy<Mutex<HashMap<IpAddr, CacheEntry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub async fn lookup(ip: IpAddr) -> Option<String> {
+    let now = Instant::now();
+    if let Some((name, at)) = cache_get(ip) {
+        if now.duration_since(at) < CACHE_TTL {
+            return name;
+        }
+    }
+
+    let resolved = match timeout(
+        LOOKUP_TIMEOUT,
+        tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip)),
+    )
+    .await
+    {
+        Ok(Ok(Ok(name))) => normalize_name(&name, ip),
+        Ok(Ok(Err(err))) => {
+            debug!("reverse DNS lookup failed for {}: {:?}", ip, err);
+            None
+        }
+        Ok(Err(err)) => {
+            debug!("reverse DNS lookup task failed for {}: {}", ip, err);
+            None
+        }
+        Err(_) => {
+            debug!("reverse DNS lookup timed out for {}", ip);
+            None
+        }
+    };
+
+    cache_put(ip, resolved.clone(), now);
+    resolved
+}
+
+fn normalize_name(name: &str, ip: IpAddr) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+    // getnameinfo may hand back the numeric form when no PTR exists.
+    if name.is_empty() || name.parse::<IpAddr>().ok() == Some(ip) {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn cache_get(ip: IpAddr) -> Option<CacheEntry> {
+    let cache = CACHE.lock().ok()?;
+    cache.get(&ip).cloned()
+}
+
+fn cache_put(ip: IpAddr, name: Option<String>, now: Instant) {
+    let Ok(mut cache) = CACHE.lock() else {
+        return;
+    };
+    if cache.len() >= CACHE_MAX_ENTRIES {
+        cache.retain(|_, (_, at)| now.duration_since(*at) < CACHE_TTL);
+        if cache.len() >= CACHE_MAX_ENTRIES {
+            cache.clear();
+        }
+    }
+    cache.insert(ip, (name, now));
+}
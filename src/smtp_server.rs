@@ -1,44 +1,86 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::Utc;
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use memmap2::Mmap;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::watch;
-use tracing::{debug, info, warn};
+use tracing::{debug, field, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
 use crate::address;
-use crate::config::Config;
+use crate::config::{Config, MailboxFullPolicy, OversizePolicy, RelayMode};
 use crate::mail_parser;
+use crate::rate_limit::SlidingWindowLimiter;
+use crate::reverse_dns;
 use crate::store::{Message, Store};
 
-#[derive(Clone)]
 struct Recipient {
     mailbox: String,
     address: String,
+    original: Option<String>,
+}
+
+struct Client {
+    addr: SocketAddr,
+    host: Option<String>,
+    helo: String,
+}
+
+const IDLE_TIMEOUT_REPLY: &[u8] = b"421 idle timeout, closing connection\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TransactionState {
+    #[default]
+    Idle,
+    MailFrom,
+    RcptTo,
 }
 
 #[derive(Default)]
 struct Transaction {
+    state: TransactionState,
     from: String,
+    quarantined_sender: bool,
     recipients: Vec<Recipient>,
+    chunks: Option<MessageBuffer>,
 }
 
 impl Transaction {
     fn reset(&mut self) {
+        self.state = TransactionState::Idle;
         self.from.clear();
+        self.quarantined_sender = false;
         self.recipients.clear();
+        self.chunks = None;
     }
 }
 
 pub async fn run(
     cfg: Arc<Config>,
     store: Store,
+    smtp_ok: Arc<AtomicBool>,
     mut shutdown_rx: watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
     let listen_addr = normalize_listen_addr(&cfg.smtp_addr);
     let listener = TcpListener::bind(&listen_addr).await?;
     info!("SMTP listening on {}", listen_addr);
+    smtp_ok.store(true, Ordering::Relaxed);
+
+    let sender_domain_limiter = Arc::new(SlidingWindowLimiter::new(
+        cfg.sender_domain_rate_limit,
+        Duration::from_secs(cfg.sender_domain_rate_window_seconds),
+    ));
+    let mut prune_ticker = tokio::time::interval(Duration::from_secs(60));
 
     loop {
         tokio::select! {
@@ -46,12 +88,18 @@ pub async fn run(
                 let (stream, peer) = result?;
                 let cfg = cfg.clone();
                 let store = store.clone();
-                tokio::spawn(async move {
-                    if let Err(err) = handle_connection(stream, cfg, store).await {
-                        warn!("SMTP connection {} error: {}", peer, err);
+                let sender_domain_limiter = sender_domain_limiter.clone();
+                let span = info_span!("smtp", peer = %peer.ip(), host = field::Empty);
+                tokio::spawn(
+                    async move {
+                        if let Err(err) = handle_connection(stream, peer, cfg, store, sender_domain_limiter).await {
+                            warn!("SMTP connection {} error: {}", peer, err);
+                        }
                     }
-                });
+                    .instrument(span),
+                );
             }
+            _ = prune_ticker.tick() => sender_domain_limiter.prune(),
             changed = shutdown_rx.changed() => {
                 if changed.is_ok() && *shutdown_rx.borrow() {
                     info!("SMTP shutdown signal received");
@@ -66,18 +114,45 @@ pub async fn run(
 
 async fn handle_connection(
     stream: TcpStream,
+    peer: SocketAddr,
     cfg: Arc<Config>,
     store: Store,
+    sender_domain_limiter: Arc<SlidingWindowLimiter>,
 ) -> anyhow::Result<()> {
     let (reader_half, mut writer_half) = stream.into_split();
     let mut reader = BufReader::new(reader_half);
     let mut line = String::new();
     let mut tx = Transaction::default();
-    let announce_domain = if cfg.domain.is_empty() {
-        "localhost"
+    let mut accepted_messages = 0usize;
+    let announce_domain = announce_domain(&cfg);
+
+    let host = if cfg.reverse_dns {
+        reverse_dns::lookup(peer.ip()).await
     } else {
-        cfg.domain.as_str()
+        None
     };
+    if let Some(host) = &host {
+        tracing::Span::current().record("host", field::display(host));
+    }
+    let mut client = Client {
+        addr: peer,
+        host,
+        helo: String::new(),
+    };
+
+    if cfg.greeting_delay_ms > 0 {
+        let delay = Duration::from_millis(cfg.greeting_delay_ms);
+        match tokio::time::timeout(delay, reader.fill_buf()).await {
+            Ok(Ok([])) => return Ok(()),
+            Ok(Ok(_)) => {
+                warn!("client sent data before greeting, disconnecting");
+                write_reply(&mut writer_half, b"554 protocol violation\r\n").await?;
+                return Ok(());
+            }
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_) => {}
+        }
+    }
 
     write_reply(
         &mut writer_half,
@@ -85,9 +160,16 @@ async fn handle_connection(
     )
     .await?;
 
+    let idle_timeout = (cfg.smtp_idle_timeout_seconds > 0)
+        .then(|| Duration::from_secs(cfg.smtp_idle_timeout_seconds));
+
     loop {
         line.clear();
-        let read = reader.read_line(&mut line).await?;
+        let Some(read) = read_line_within(&mut reader, &mut line, idle_timeout).await? else {
+            info!("SMTP session idle timeout");
+            write_reply(&mut writer_half, IDLE_TIMEOUT_REPLY).await?;
+            break;
+        };
         if read == 0 {
             break;
         }
@@ -99,20 +181,40 @@ async fn handle_connection(
         let (verb, arg) = split_command(input);
         match verb.as_str() {
             "EHLO" => {
+                tx.reset();
+                client.helo = arg.to_string();
+                let chunking = if cfg.smtp_chunking {
+                    "250-CHUNKING\r\n"
+                } else {
+                    ""
+                };
                 let response = format!(
-                    "250-{}\r\n250-SIZE {}\r\n250 8BITMIME\r\n",
-                    announce_domain, cfg.max_message_bytes
+                    "250-{}\r\n250-PIPELINING\r\n250-SIZE {}\r\n{}250 8BITMIME\r\n",
+                    announce_domain, cfg.max_message_bytes, chunking
                 );
                 write_reply(&mut writer_half, response.as_bytes()).await?;
             }
             "HELO" => {
+                tx.reset();
+                client.helo = arg.to_string();
                 write_reply(
                     &mut writer_half,
                     format!("250 {}\r\n", announce_domain).as_bytes(),
                 )
                 .await?;
             }
-            "MAIL" => match handle_mail_from(&cfg, &mut tx, arg) {
+            "MAIL"
+                if cfg.smtp_max_messages_per_connection > 0
+                    && accepted_messages >= cfg.smtp_max_messages_per_connection =>
+            {
+                info!(
+                    accepted_messages,
+                    "SMTP per-connection message limit reached"
+                );
+                write_reply(&mut writer_half, b"421 too many messages, reconnect\r\n").await?;
+                break;
+            }
+            "MAIL" => match handle_mail_from(&cfg, &sender_domain_limiter, &mut tx, arg) {
                 Ok(_) => write_reply(&mut writer_half, b"250 OK\r\n").await?,
                 Err((code, message)) => {
                     write_reply(
@@ -122,7 +224,7 @@ async fn handle_connection(
                     .await?
                 }
             },
-            "RCPT" => match handle_rcpt_to(&cfg, &mut tx, arg) {
+            "RCPT" => match handle_rcpt_to(&cfg, &store, &mut tx, arg).await {
                 Ok(_) => write_reply(&mut writer_half, b"250 OK\r\n").await?,
                 Err((code, message)) => {
                     write_reply(
@@ -133,60 +235,113 @@ async fn handle_connection(
                 }
             },
             "DATA" => {
-                if tx.recipients.is_empty() {
-                    write_reply(&mut writer_half, b"554 no recipients\r\n").await?;
-                    continue;
+                match tx.state {
+                    TransactionState::Idle => {
+                        write_reply(&mut writer_half, b"503 need MAIL command\r\n").await?;
+                        continue;
+                    }
+                    TransactionState::MailFrom => {
+                        write_reply(&mut writer_half, b"554 no recipients\r\n").await?;
+                        continue;
+                    }
+                    TransactionState::RcptTo if tx.chunks.is_some() => {
+                        write_reply(&mut writer_half, b"503 BDAT transfer in progress\r\n").await?;
+                        continue;
+                    }
+                    TransactionState::RcptTo => {}
                 }
 
                 write_reply(&mut writer_half, b"354 End data with <CR><LF>.<CR><LF>\r\n").await?;
 
-                match read_data_block(&mut reader, cfg.max_message_bytes).await {
-                    Ok(raw_message) => match mail_parser::parse(&raw_message) {
-                        Ok(parsed) => {
-                            let now = Utc::now();
-                            for rcpt in &tx.recipients {
-                                let mut msg = Message {
-                                    id: Uuid::new_v4().simple().to_string(),
-                                    mailbox: rcpt.mailbox.clone(),
-                                    to: rcpt.address.clone(),
-                                    from: parsed.from.clone(),
-                                    subject: parsed.subject.clone(),
-                                    date: parsed.date,
-                                    text: parsed.text.clone(),
-                                    html: parsed.html.clone(),
-                                    headers: parsed.headers.clone(),
-                                    received_at: now,
-                                };
-
-                                if msg.from.trim().is_empty() {
-                                    msg.from = tx.from.clone();
-                                }
-                                if msg.date.timestamp() == 0 {
-                                    msg.date = now;
-                                }
-
-                                store.add(&rcpt.mailbox, msg).await;
-                                info!(
-                                    "mail received mailbox={} from={} subject={}",
-                                    rcpt.mailbox, tx.from, parsed.subject
-                                );
-                            }
-                            tx.reset();
-                            write_reply(&mut writer_half, b"250 message accepted\r\n").await?;
+                let result = match read_data_block(
+                    &mut reader,
+                    cfg.max_message_bytes,
+                    cfg.spool_to_disk_bytes,
+                    idle_timeout,
+                )
+                .await
+                {
+                    Ok(block) => deliver_block(&cfg, &store, &client, &tx, block).await,
+                    Err(err) => Err(err),
+                };
+                tx.reset();
+
+                match result {
+                    Ok(()) => {
+                        accepted_messages += 1;
+                        write_reply(&mut writer_half, b"250 message accepted\r\n").await?
+                    }
+                    Err((code, message)) => {
+                        write_reply(
+                            &mut writer_half,
+                            format!("{} {}\r\n", code, message).as_bytes(),
+                        )
+                        .await?;
+                        if code == 421 {
+                            break;
+                        }
+                    }
+                }
+            }
+            "BDAT" if cfg.smtp_chunking => {
+                let Some((size, last)) = parse_bdat_args(arg) else {
+                    write_reply(&mut writer_half, b"501 syntax: BDAT <size> [LAST]\r\n").await?;
+                    continue;
+                };
+
+                // size is client-supplied, so compare against the remaining room
+                // rather than adding to it.
+                let received = tx.chunks.as_ref().map_or(0, MessageBuffer::len);
+                let fits = size <= (cfg.max_message_bytes as u64).saturating_sub(received as u64);
+                let keep = if tx.state == TransactionState::RcptTo && fits {
+                    Some(
+                        tx.chunks
+                            .get_or_insert_with(|| MessageBuffer::new(cfg.spool_to_disk_bytes)),
+                    )
+                } else {
+                    None
+                };
+                let result = match read_chunk(&mut reader, size, keep, idle_timeout).await {
+                    Err(err) => Err(err),
+                    Ok(()) => match tx.state {
+                        TransactionState::Idle => Err((503, "need MAIL command".to_string())),
+                        TransactionState::MailFrom => Err((554, "no recipients".to_string())),
+                        TransactionState::RcptTo if !fits => {
+                            Err((552, message_too_large(cfg.max_message_bytes)))
                         }
-                        Err(_) => {
-                            tx.reset();
-                            write_reply(&mut writer_half, b"550 invalid message content\r\n")
-                                .await?;
+                        TransactionState::RcptTo if !last => {
+                            write_reply(
+                                &mut writer_half,
+                                format!("250 {} octets received\r\n", size).as_bytes(),
+                            )
+                            .await?;
+                            continue;
                         }
+                        TransactionState::RcptTo => match tx.chunks.take() {
+                            Some(buffer) => match buffer.finish().await {
+                                Ok(block) => deliver_block(&cfg, &store, &client, &tx, block).await,
+                                Err(err) => Err(err),
+                            },
+                            None => Err((451, "failed to read message".to_string())),
+                        },
                     },
+                };
+                tx.reset();
+
+                match result {
+                    Ok(()) => {
+                        accepted_messages += 1;
+                        write_reply(&mut writer_half, b"250 message accepted\r\n").await?
+                    }
                     Err((code, message)) => {
-                        tx.reset();
                         write_reply(
                             &mut writer_half,
                             format!("{} {}\r\n", code, message).as_bytes(),
                         )
                         .await?;
+                        if code == 421 {
+                            break;
+                        }
                     }
                 }
             }
@@ -209,54 +364,243 @@ async fn handle_connection(
     Ok(())
 }
 
-fn handle_mail_from(cfg: &Config, tx: &mut Transaction, arg: &str) -> Result<(), (u16, String)> {
+async fn deliver(
+    cfg: &Config,
+    store: &Store,
+    client: &Client,
+    tx: &Transaction,
+    raw_message: &[u8],
+) -> Result<(), (u16, String)> {
+    let mut parsed = mail_parser::parse(raw_message, &cfg.parse_options())
+        .map_err(|_| (550, "invalid message content".to_string()))?;
+    if parsed.truncated && cfg.oversize_policy == OversizePolicy::Reject {
+        info!(
+            "rejecting message with body part over {} bytes",
+            cfg.max_body_part_bytes
+        );
+        return Err((
+            552,
+            format!(
+                "message body part exceeds {} bytes",
+                cfg.max_body_part_bytes
+            ),
+        ));
+    }
+    if cfg.require_from_header && parsed.from.trim().is_empty() {
+        return Err((550, "missing From header".to_string()));
+    }
+
+    if cfg.strip_incoming_received {
+        parsed
+            .headers
+            .retain(|key, _| !key.eq_ignore_ascii_case("Received"));
+    }
+
+    let now = Utc::now();
+    prepend_header(
+        &mut parsed.headers,
+        "Received",
+        received_header(client, announce_domain(cfg), now),
+    );
+
+    for DeliveryGroup {
+        rcpt,
+        delivered_to,
+        original_recipients,
+    } in group_recipients(&tx.recipients)
+    {
+        let mut msg = Message {
+            id: Uuid::new_v4().simple().to_string(),
+            mailbox: rcpt.mailbox.clone(),
+            envelope_to: rcpt.address.clone(),
+            header_to: parsed.to.clone(),
+            delivered_to,
+            original_recipients: original_recipients.clone(),
+            envelope_from: tx.from.clone(),
+            from: parsed.from.clone(),
+            subject: parsed.subject.clone(),
+            date: parsed.date,
+            date_raw: parsed.date_raw.clone(),
+            date_parse_failed: parsed.date_parse_failed,
+            text: parsed.text.clone(),
+            html: parsed.html.clone(),
+            headers: parsed.headers.clone(),
+            labels: Vec::new(),
+            truncated: parsed.truncated,
+            received_at: now,
+        };
+
+        if msg.from.trim().is_empty() {
+            msg.from = tx.from.clone();
+        }
+        if msg.date.timestamp() == 0 {
+            msg.date = now;
+        }
+
+        store.add(&rcpt.mailbox, msg).await;
+        if original_recipients.is_empty() {
+            info!(
+                "mail received mailbox={} from={} subject={}",
+                rcpt.mailbox, tx.from, parsed.subject
+            );
+        } else {
+            info!(
+                "mail quarantined mailbox={} original={} from={} subject={}",
+                rcpt.mailbox,
+                original_recipients.join(","),
+                tx.from,
+                parsed.subject
+            );
+        }
+    }
+
+    Ok(())
+}
+
+struct DeliveryGroup<'a> {
+    rcpt: &'a Recipient,
+    delivered_to: Vec<String>,
+    original_recipients: Vec<String>,
+}
+
+// One copy per target mailbox; quarantined recipients that land in the
+// same mailbox are all listed on that copy.
+fn group_recipients(recipients: &[Recipient]) -> Vec<DeliveryGroup<'_>> {
+    let mut groups: Vec<DeliveryGroup> = Vec::new();
+    for rcpt in recipients {
+        let index = match groups
+            .iter()
+            .position(|group| group.rcpt.mailbox == rcpt.mailbox)
+        {
+            Some(index) => index,
+            None => {
+                groups.push(DeliveryGroup {
+                    rcpt,
+                    delivered_to: Vec::new(),
+                    original_recipients: Vec::new(),
+                });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index];
+        if !group.delivered_to.contains(&rcpt.address) {
+            group.delivered_to.push(rcpt.address.clone());
+        }
+        if let Some(original) = &rcpt.original {
+            if !group.original_recipients.contains(original) {
+                group.original_recipients.push(original.clone());
+            }
+        }
+    }
+    groups
+}
+
+fn handle_mail_from(
+    cfg: &Config,
+    sender_domain_limiter: &SlidingWindowLimiter,
+    tx: &mut Transaction,
+    arg: &str,
+) -> Result<(), (u16, String)> {
+    if tx.state != TransactionState::Idle {
+        return Err((503, "nested MAIL command".to_string()));
+    }
+
+    if let Some(window) = &cfg.accept_window {
+        if !window.contains(Utc::now()) {
+            tx.reset();
+            return Err((421, "service not available at this time".to_string()));
+        }
+    }
+
     let from = extract_smtp_address(arg, "FROM:").map_err(|msg| (550, msg))?;
     tx.recipients.clear();
 
+    if declared_size(arg).is_some_and(|size| size > cfg.max_message_bytes) {
+        return Err((552, message_too_large(cfg.max_message_bytes)));
+    }
+
     if from.is_empty() {
         tx.from.clear();
+        tx.state = TransactionState::MailFrom;
         return Ok(());
     }
 
     let (_, domain) =
         address::parse_email(&from).map_err(|_| (550, "invalid sender address".to_string()))?;
-    if cfg.is_sender_domain_blocked(&domain) {
+    let quarantined_sender = cfg.is_sender_domain_blocked(&domain);
+    if quarantined_sender && cfg.quarantine_mailbox.is_empty() {
         return Err((530, "sender domain is blocked".to_string()));
     }
+    if !sender_domain_limiter.check(&domain) {
+        tx.reset();
+        warn!("sender domain rate limited: {}", domain);
+        return Err((421, "sender domain rate limited".to_string()));
+    }
 
     tx.from = from.to_ascii_lowercase();
+    tx.quarantined_sender = quarantined_sender;
+    tx.state = TransactionState::MailFrom;
     Ok(())
 }
 
-fn handle_rcpt_to(cfg: &Config, tx: &mut Transaction, arg: &str) -> Result<(), (u16, String)> {
+async fn handle_rcpt_to(
+    cfg: &Config,
+    store: &Store,
+    tx: &mut Transaction,
+    arg: &str,
+) -> Result<(), (u16, String)> {
+    if tx.state == TransactionState::Idle {
+        return Err((503, "need MAIL before RCPT".to_string()));
+    }
+
     let to = extract_smtp_address(arg, "TO:").map_err(|msg| (550, msg))?;
-    let (mailbox, email_address) =
-        address::normalize_mailbox(&to, &cfg.domain).map_err(|msg| (550, msg))?;
+    if cfg.relay_mode == RelayMode::RequireLocalDomain && !cfg.is_local_recipient(&to) {
+        info!("relaying denied for recipient {}", to);
+        return Err((550, "relaying denied".to_string()));
+    }
+
+    let (mut mailbox, email_address) =
+        address::normalize_mailbox(&to, &cfg.mailbox_options()).map_err(|msg| (550, msg))?;
 
-    if cfg.is_mailbox_blacklisted(&mailbox) {
-        return Err((550, "mailbox is blocked".to_string()));
+    let mut original = None;
+    if tx.quarantined_sender || cfg.is_mailbox_blacklisted(&mailbox) {
+        if cfg.quarantine_mailbox.is_empty() {
+            return Err((550, "mailbox is blocked".to_string()));
+        }
+        mailbox = cfg.quarantine_mailbox.clone();
+        original = Some(email_address.clone());
+    }
+
+    if cfg.mailbox_full_policy == MailboxFullPolicy::Reject && store.is_full(&mailbox).await {
+        info!("mailbox {} is full, rejecting recipient", mailbox);
+        return Err((452, "mailbox full".to_string()));
     }
 
     tx.recipients.push(Recipient {
         mailbox,
         address: email_address,
+        original,
     });
+    tx.state = TransactionState::RcptTo;
     Ok(())
 }
 
 async fn read_data_block<R: AsyncBufRead + Unpin>(
     reader: &mut R,
     max_message_bytes: usize,
-) -> Result<Vec<u8>, (u16, String)> {
-    let mut raw = Vec::new();
+    spool_to_disk_bytes: usize,
+    idle_timeout: Option<Duration>,
+) -> Result<DataBlock, (u16, String)> {
+    let mut buffer = Some(MessageBuffer::new(spool_to_disk_bytes));
+    let mut total = 0;
     let mut line = String::new();
 
     loop {
         line.clear();
-        let read = reader
-            .read_line(&mut line)
+        let read = read_line_within(reader, &mut line, idle_timeout)
             .await
-            .map_err(|_| (451, "failed to read message".to_string()))?;
+            .map_err(|_| (451, "failed to read message".to_string()))?
+            .ok_or_else(|| (421, "idle timeout, closing connection".to_string()))?;
         if read == 0 {
             return Err((451, "message terminated unexpectedly".to_string()));
         }
@@ -269,14 +613,295 @@ async fn read_data_block<R: AsyncBufRead + Unpin>(
         if bytes.starts_with(b"..") {
             bytes.remove(0);
         }
-        raw.extend_from_slice(&bytes);
+        total += bytes.len();
 
-        if raw.len() > max_message_bytes {
-            return Err((552, "message too large".to_string()));
+        if total > max_message_bytes {
+            // Keep draining to the terminating dot so the rest of the
+            // message is not read as commands.
+            buffer = None;
+            continue;
+        }
+
+        if let Some(buffer) = buffer.as_mut() {
+            buffer.write(&bytes).await?;
+        }
+    }
+
+    match buffer {
+        Some(buffer) if total <= max_message_bytes => buffer.finish().await,
+        _ => Err((552, message_too_large(max_message_bytes))),
+    }
+}
+
+// Holds a DATA or BDAT body in memory until it passes spool_to_disk_bytes,
+// then moves it to a spool file.
+struct MessageBuffer {
+    raw: Vec<u8>,
+    spool: Option<(BufWriter<File>, SpoolFile)>,
+    len: usize,
+    spool_to_disk_bytes: usize,
+}
+
+impl MessageBuffer {
+    fn new(spool_to_disk_bytes: usize) -> Self {
+        Self {
+            raw: Vec::new(),
+            spool: None,
+            len: 0,
+            spool_to_disk_bytes,
         }
     }
 
-    Ok(raw)
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), (u16, String)> {
+        self.len += bytes.len();
+        match self.spool.as_mut() {
+            Some((writer, _)) => writer
+                .write_all(bytes)
+                .await
+                .map_err(|_| (451, "failed to spool message".to_string())),
+            None => {
+                self.raw.extend_from_slice(bytes);
+                if self.spool_to_disk_bytes > 0 && self.raw.len() > self.spool_to_disk_bytes {
+                    let (mut writer, spool_file) = SpoolFile::create()
+                        .await
+                        .map_err(|_| (451, "failed to spool message".to_string()))?;
+                    writer
+                        .write_all(&self.raw)
+                        .await
+                        .map_err(|_| (451, "failed to spool message".to_string()))?;
+                    self.raw = Vec::new();
+                    self.spool = Some((writer, spool_file));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn finish(self) -> Result<DataBlock, (u16, String)> {
+        match self.spool {
+            Some((mut writer, spool_file)) => {
+                writer
+                    .flush()
+                    .await
+                    .map_err(|_| (451, "failed to spool message".to_string()))?;
+                drop(writer);
+                Ok(DataBlock::Spooled(spool_file))
+            }
+            None => Ok(DataBlock::Memory(self.raw)),
+        }
+    }
+}
+
+async fn deliver_block(
+    cfg: &Config,
+    store: &Store,
+    client: &Client,
+    tx: &Transaction,
+    block: DataBlock,
+) -> Result<(), (u16, String)> {
+    match block.contents().await {
+        Ok(raw_message) => deliver(cfg, store, client, tx, &raw_message).await,
+        Err(err) => {
+            warn!("failed to read spooled message: {}", err);
+            Err((451, "failed to read message".to_string()))
+        }
+    }
+}
+
+enum DataBlock {
+    Memory(Vec<u8>),
+    Spooled(SpoolFile),
+}
+
+impl DataBlock {
+    async fn contents(&self) -> std::io::Result<DataContents<'_>> {
+        match self {
+            DataBlock::Memory(raw) => Ok(DataContents::Memory(raw.as_slice())),
+            DataBlock::Spooled(spool_file) => {
+                let file = std::fs::File::open(&spool_file.path)?;
+                // SAFETY: the spool file is private to this session and is not
+                // written again after the DATA block is flushed.
+                let map = unsafe { Mmap::map(&file)? };
+                Ok(DataContents::Mapped(map))
+            }
+        }
+    }
+}
+
+enum DataContents<'a> {
+    Memory(&'a [u8]),
+    Mapped(Mmap),
+}
+
+impl Deref for DataContents<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            DataContents::Memory(raw) => raw,
+            DataContents::Mapped(map) => map,
+        }
+    }
+}
+
+struct SpoolFile {
+    path: PathBuf,
+}
+
+impl SpoolFile {
+    async fn create() -> std::io::Result<(BufWriter<File>, SpoolFile)> {
+        let path =
+            std::env::temp_dir().join(format!("forsaken-mail-{}.eml", Uuid::new_v4().simple()));
+        // Captured mail must not be readable by other local users, and
+        // create_new refuses a pre-created file or symlink at the path.
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(&path).await?;
+        debug!("spooling message to {}", path.display());
+        Ok((BufWriter::new(file), SpoolFile { path }))
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "failed to remove spool file {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn received_header(client: &Client, announce_domain: &str, now: chrono::DateTime<Utc>) -> String {
+    let helo = if client.helo.is_empty() {
+        "unknown"
+    } else {
+        client.helo.as_str()
+    };
+    let remote = match &client.host {
+        Some(host) => format!("{} [{}]", host, client.addr.ip()),
+        None => format!("[{}]", client.addr.ip()),
+    };
+    format!(
+        "from {} ({}) by {} with ESMTP; {}",
+        helo,
+        remote,
+        announce_domain,
+        now.to_rfc2822()
+    )
+}
+
+fn prepend_header(headers: &mut HashMap<String, Vec<String>>, key: &str, value: String) {
+    let existing_key = headers
+        .keys()
+        .find(|k| k.eq_ignore_ascii_case(key))
+        .cloned()
+        .unwrap_or_else(|| key.to_string());
+    headers.entry(existing_key).or_default().insert(0, value);
+}
+
+fn declared_size(arg: &str) -> Option<usize> {
+    arg.split_whitespace()
+        .skip(1)
+        .find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            key.eq_ignore_ascii_case("SIZE").then_some(value)
+        })
+        .and_then(|value| value.parse::<usize>().ok())
+}
+
+fn message_too_large(max_message_bytes: usize) -> String {
+    format!(
+        "message exceeds maximum size of {} bytes",
+        max_message_bytes
+    )
+}
+
+async fn read_chunk<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    size: u64,
+    mut keep: Option<&mut MessageBuffer>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), (u16, String)> {
+    let mut buf = vec![0; size.min(64 * 1024) as usize];
+    let mut remaining = size;
+    let mut failed = None;
+
+    while remaining > 0 {
+        let want = remaining.min(buf.len() as u64) as usize;
+        let read = read_within(reader, &mut buf[..want], idle_timeout)
+            .await
+            .map_err(|_| (451, "failed to read message".to_string()))?
+            .ok_or_else(|| (421, "idle timeout, closing connection".to_string()))?;
+        if read == 0 {
+            return Err((451, "message terminated unexpectedly".to_string()));
+        }
+        remaining -= read as u64;
+
+        // Keep draining after a spool failure so the rest of the chunk is
+        // not read as commands.
+        if let Some(buffer) = keep.as_mut() {
+            if let Err(err) = buffer.write(&buf[..read]).await {
+                failed = Some(err);
+                keep = None;
+            }
+        }
+    }
+
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn parse_bdat_args(arg: &str) -> Option<(u64, bool)> {
+    let mut parts = arg.split_whitespace();
+    let size = parts.next()?.parse().ok()?;
+    let last = match parts.next() {
+        None => false,
+        Some(value) if value.eq_ignore_ascii_case("LAST") => true,
+        Some(_) => return None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((size, last))
+}
+
+async fn read_within<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+    idle_timeout: Option<Duration>,
+) -> std::io::Result<Option<usize>> {
+    match idle_timeout {
+        Some(limit) => match tokio::time::timeout(limit, reader.read(buf)).await {
+            Ok(read) => read.map(Some),
+            Err(_) => Ok(None),
+        },
+        None => reader.read(buf).await.map(Some),
+    }
+}
+
+async fn read_line_within<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+    idle_timeout: Option<Duration>,
+) -> std::io::Result<Option<usize>> {
+    match idle_timeout {
+        Some(limit) => match tokio::time::timeout(limit, reader.read_line(line)).await {
+            Ok(read) => read.map(Some),
+            Err(_) => Ok(None),
+        },
+        None => reader.read_line(line).await.map(Some),
+    }
 }
 
 fn split_command(input: &str) -> (String, &str) {
@@ -319,6 +944,14 @@ async fn write_reply<W: AsyncWrite + Unpin>(writer: &mut W, reply: &[u8]) -> any
     Ok(())
 }
 
+fn announce_domain(cfg: &Config) -> &str {
+    if cfg.domain.is_empty() {
+        "localhost"
+    } else {
+        cfg.domain.as_str()
+    }
+}
+
 fn normalize_listen_addr(addr: &str) -> String {
     if addr.starts_with(':') {
         format!("0.0.0.0{}", addr)
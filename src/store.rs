@@ -1,60 +1,102 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
 use chrono::{DateTime, Duration, Utc};
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::sync::broadcast;
 use tokio::sync::RwLock;
 
-#[derive(Debug, Clone, Serialize)]
+pub const MAX_LABELS_PER_MESSAGE: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: String,
     pub mailbox: String,
-    pub to: String,
+    #[serde(alias = "to")]
+    pub envelope_to: String,
+    #[serde(default)]
+    pub header_to: String,
+    pub delivered_to: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub original_recipients: Vec<String>,
+    #[serde(default)]
+    pub envelope_from: String,
     pub from: String,
     pub subject: String,
     pub date: DateTime<Utc>,
+    #[serde(default)]
+    pub date_raw: Option<String>,
+    #[serde(default)]
+    pub date_parse_failed: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub html: Option<String>,
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub headers: HashMap<String, Vec<String>>,
+    pub labels: Vec<String>,
+    pub truncated: bool,
     pub received_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageSummary {
     pub id: String,
+    #[serde(default)]
+    pub envelope_to: String,
+    #[serde(default)]
+    pub header_to: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub original_recipients: Vec<String>,
     pub from: String,
     pub subject: String,
     pub date: DateTime<Utc>,
     pub has_html: bool,
     pub preview: String,
+    pub labels: Vec<String>,
     pub received_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum StoreEventType {
     Added,
     Deleted,
     Cleared,
+    Updated,
+}
+
+#[derive(Debug)]
+pub enum LabelError {
+    NotFound,
+    TooMany,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoreEvent {
     pub event: StoreEventType,
     pub mailbox: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
     pub at: DateTime<Utc>,
 }
 
+#[derive(Default)]
+struct MailboxMessages {
+    order: VecDeque<String>,
+    by_id: HashMap<String, Message>,
+}
+
 #[derive(Default)]
 struct StoreInner {
-    by_mailbox: HashMap<String, Vec<Message>>,
+    by_mailbox: HashMap<String, MailboxMessages>,
+    by_message_id: HashMap<String, Vec<(String, String)>>,
 }
 
 #[derive(Clone)]
@@ -62,23 +104,34 @@ pub struct Store {
     inner: Arc<RwLock<StoreInner>>,
     max_messages: usize,
     ttl: Duration,
+    case_sensitive: bool,
     events_tx: broadcast::Sender<StoreEvent>,
 }
 
 impl Store {
-    pub fn new(max_messages: usize, ttl_minutes: i64) -> Self {
+    pub fn new(max_messages: usize, ttl_minutes: i64, case_sensitive: bool) -> Self {
         let (events_tx, _) = broadcast::channel(1024);
         Self {
             inner: Arc::new(RwLock::new(StoreInner::default())),
             max_messages,
             ttl: Duration::minutes(ttl_minutes.max(1)),
+            case_sensitive,
             events_tx,
         }
     }
 
+    fn mailbox_key(&self, mailbox: &str) -> String {
+        let mailbox = mailbox.trim();
+        if self.case_sensitive {
+            mailbox.to_string()
+        } else {
+            mailbox.to_ascii_lowercase()
+        }
+    }
+
     pub async fn add(&self, mailbox: &str, mut message: Message) {
         let now = Utc::now();
-        let mailbox = mailbox.trim().to_ascii_lowercase();
+        let mailbox = self.mailbox_key(mailbox);
 
         if message.received_at.timestamp() == 0 {
             message.received_at = now;
@@ -91,152 +144,270 @@ impl Store {
         }
 
         let message_id = message.id.clone();
+        let from = message.from.clone();
+        let subject = message.subject.clone();
         let mut inner = self.inner.write().await;
-        inner
-            .by_mailbox
-            .entry(mailbox.clone())
-            .or_default()
-            .push(message);
-        prune_mailbox(
-            &mut inner.by_mailbox,
-            &mailbox,
-            now,
-            self.ttl,
-            self.max_messages,
-        );
+        inner.insert(&mailbox, message);
+        inner.prune(&mailbox, now - self.ttl, self.max_messages);
 
         let _ = self.events_tx.send(StoreEvent {
             event: StoreEventType::Added,
             mailbox,
             message_id: Some(message_id),
+            from: Some(from),
+            subject: Some(subject),
             at: now,
         });
     }
 
     pub async fn list(&self, mailbox: &str) -> Vec<Message> {
-        let mailbox = mailbox.trim().to_ascii_lowercase();
-        let now = Utc::now();
-        let mut inner = self.inner.write().await;
-        prune_mailbox(
-            &mut inner.by_mailbox,
-            &mailbox,
-            now,
-            self.ttl,
-            self.max_messages,
-        );
+        let mailbox = self.mailbox_key(mailbox);
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
 
         inner
             .by_mailbox
             .get(&mailbox)
-            .map(|messages| messages.iter().rev().cloned().collect())
+            .map(|messages| messages.live(cutoff).cloned().collect())
             .unwrap_or_default()
     }
 
     pub async fn get(&self, mailbox: &str, id: &str) -> Option<Message> {
-        let mailbox = mailbox.trim().to_ascii_lowercase();
-        let now = Utc::now();
-        let mut inner = self.inner.write().await;
-        prune_mailbox(
-            &mut inner.by_mailbox,
-            &mailbox,
-            now,
-            self.ttl,
-            self.max_messages,
-        );
+        let mailbox = self.mailbox_key(mailbox);
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
 
         inner
             .by_mailbox
             .get(&mailbox)
-            .and_then(|messages| messages.iter().rev().find(|item| item.id == id))
+            .and_then(|messages| messages.by_id.get(id))
+            .filter(|item| item.received_at >= cutoff)
             .cloned()
     }
 
-    pub async fn cleanup_expired(&self) -> usize {
+    pub async fn count(&self, mailbox: &str) -> usize {
+        let mailbox = self.mailbox_key(mailbox);
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
+
+        inner
+            .by_mailbox
+            .get(&mailbox)
+            .map_or(0, |messages| messages.live(cutoff).count())
+    }
+
+    pub async fn is_full(&self, mailbox: &str) -> bool {
+        let mailbox = self.mailbox_key(mailbox);
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
+        inner.by_mailbox.get(&mailbox).is_some_and(|messages| {
+            messages.live(cutoff).take(self.max_messages).count() >= self.max_messages
+        })
+    }
+
+    pub async fn latest(&self, mailbox: &str, offset: usize) -> Option<Message> {
+        let mailbox = self.mailbox_key(mailbox);
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
+
+        inner
+            .by_mailbox
+            .get(&mailbox)
+            .and_then(|messages| messages.live(cutoff).nth(offset))
+            .cloned()
+    }
+
+    pub async fn update_labels(
+        &self,
+        mailbox: &str,
+        id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<Vec<String>, LabelError> {
+        let mailbox = self.mailbox_key(mailbox);
         let now = Utc::now();
         let mut inner = self.inner.write().await;
-        let keys: Vec<String> = inner.by_mailbox.keys().cloned().collect();
-        let mut removed = 0;
+        inner.prune(&mailbox, now - self.ttl, self.max_messages);
 
-        for mailbox in keys {
-            let before = inner.by_mailbox.get(&mailbox).map_or(0, Vec::len);
-            prune_mailbox(
-                &mut inner.by_mailbox,
-                &mailbox,
-                now,
-                self.ttl,
-                self.max_messages,
-            );
-            let after = inner.by_mailbox.get(&mailbox).map_or(0, Vec::len);
-            removed += before.saturating_sub(after);
+        let message = inner
+            .by_mailbox
+            .get_mut(&mailbox)
+            .and_then(|messages| messages.by_id.get_mut(id))
+            .ok_or(LabelError::NotFound)?;
+
+        let mut labels = message.labels.clone();
+        labels.retain(|label| !remove.contains(label));
+        for label in add {
+            if !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        if labels.len() > MAX_LABELS_PER_MESSAGE {
+            return Err(LabelError::TooMany);
         }
 
-        removed
+        let changed = labels != message.labels;
+        message.labels = labels.clone();
+
+        if changed {
+            let _ = self.events_tx.send(StoreEvent {
+                event: StoreEventType::Updated,
+                mailbox,
+                message_id: Some(id.to_string()),
+                from: None,
+                subject: None,
+                at: now,
+            });
+        }
+
+        Ok(labels)
     }
 
-    pub async fn delete(&self, mailbox: &str, id: &str) -> bool {
-        let mailbox = mailbox.trim().to_ascii_lowercase();
-        let id = id.trim();
-        if id.is_empty() {
-            return false;
+    pub async fn find_by_message_id(&self, message_id: &str) -> Option<(String, Message)> {
+        let wanted = normalize_message_id(message_id);
+        if wanted.is_empty() {
+            return None;
         }
 
+        let cutoff = Utc::now() - self.ttl;
+        let inner = self.inner.read().await;
+        inner
+            .by_message_id
+            .get(&wanted)?
+            .iter()
+            .rev()
+            .find_map(|(mailbox, id)| {
+                inner
+                    .by_mailbox
+                    .get(mailbox)
+                    .and_then(|messages| messages.by_id.get(id))
+                    .filter(|item| item.received_at >= cutoff)
+                    .map(|item| (mailbox.clone(), item.clone()))
+            })
+    }
+
+    pub async fn cleanup_expired(&self) -> usize {
+        let cutoff = Utc::now() - self.ttl;
         let mut inner = self.inner.write().await;
-        let (deleted, mailbox_empty) = {
-            let Some(messages) = inner.by_mailbox.get_mut(&mailbox) else {
-                return false;
-            };
+        let keys: Vec<String> = inner.by_mailbox.keys().cloned().collect();
 
-            let before = messages.len();
-            messages.retain(|item| item.id != id);
-            (messages.len() != before, messages.is_empty())
-        };
+        keys.iter()
+            .map(|mailbox| inner.prune(mailbox, cutoff, self.max_messages))
+            .sum()
+    }
 
-        if !deleted {
-            return false;
-        }
+    pub async fn delete(&self, mailbox: &str, id: &str, if_match: Option<&str>) -> (bool, usize) {
+        let mailbox = self.mailbox_key(mailbox);
+        let id = id.trim();
 
-        if mailbox_empty {
-            inner.by_mailbox.remove(&mailbox);
+        let now = Utc::now();
+        let mut inner = self.inner.write().await;
+        inner.prune(&mailbox, now - self.ttl, self.max_messages);
+
+        let Some(messages) = inner.by_mailbox.get(&mailbox) else {
+            return (false, 0);
+        };
+        let matches = messages
+            .by_id
+            .get(id)
+            .is_some_and(|item| if_match.is_none_or(|tags| etag_matches(tags, item)));
+        if !matches {
+            return (false, messages.by_id.len());
         }
 
+        inner.remove(&mailbox, id);
+        let remaining = inner
+            .by_mailbox
+            .get(&mailbox)
+            .map_or(0, |messages| messages.by_id.len());
+
         let _ = self.events_tx.send(StoreEvent {
             event: StoreEventType::Deleted,
             mailbox,
             message_id: Some(id.to_string()),
-            at: Utc::now(),
+            from: None,
+            subject: None,
+            at: now,
         });
 
-        true
+        (true, remaining)
     }
 
     pub async fn clear(&self, mailbox: &str) -> usize {
-        let mailbox = mailbox.trim().to_ascii_lowercase();
+        let mailbox = self.mailbox_key(mailbox);
         let mut inner = self.inner.write().await;
-        let removed = inner
-            .by_mailbox
-            .remove(&mailbox)
-            .map_or(0, |items| items.len());
+        let removed = inner.remove_mailbox(&mailbox);
         if removed > 0 {
             let _ = self.events_tx.send(StoreEvent {
                 event: StoreEventType::Cleared,
                 mailbox,
                 message_id: None,
+                from: None,
+                subject: None,
                 at: Utc::now(),
             });
         }
         removed
     }
 
+    pub async fn clear_all(&self) -> (usize, usize) {
+        let mut inner = self.inner.write().await;
+        let drained = std::mem::take(&mut inner.by_mailbox);
+        inner.by_message_id.clear();
+        let now = Utc::now();
+        let mut removed = 0;
+        let mailboxes = drained.len();
+
+        for (mailbox, items) in drained {
+            removed += items.by_id.len();
+            let _ = self.events_tx.send(StoreEvent {
+                event: StoreEventType::Cleared,
+                mailbox,
+                message_id: None,
+                from: None,
+                subject: None,
+                at: now,
+            });
+        }
+
+        (removed, mailboxes)
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<StoreEvent> {
         self.events_tx.subscribe()
     }
 }
 
 impl Message {
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find_map(|(header_key, values)| {
+            if header_key.eq_ignore_ascii_case(key) {
+                values.first().map(String::as_str)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn etag(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.id.as_bytes());
+        for label in &self.labels {
+            hasher.update([0]);
+            hasher.update(label.as_bytes());
+        }
+        let digest = hasher.finalize();
+        let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", hex)
+    }
+
     pub fn summary(&self) -> MessageSummary {
         let preview = build_preview(self.text.as_deref(), self.html.as_deref());
         MessageSummary {
             id: self.id.clone(),
+            envelope_to: self.envelope_to.clone(),
+            header_to: self.header_to.clone(),
+            original_recipients: self.original_recipients.clone(),
             from: self.from.clone(),
             subject: self.subject.clone(),
             date: self.date,
@@ -246,34 +417,111 @@ impl Message {
                 .map(|value| !value.trim().is_empty())
                 .unwrap_or(false),
             preview,
+            labels: self.labels.clone(),
             received_at: self.received_at,
         }
     }
 }
 
-fn prune_mailbox(
-    by_mailbox: &mut HashMap<String, Vec<Message>>,
-    mailbox: &str,
-    now: DateTime<Utc>,
-    ttl: Duration,
-    max_messages: usize,
-) {
-    let mut messages = match by_mailbox.remove(mailbox) {
-        Some(value) => value,
-        None => return,
-    };
-
-    let cutoff = now - ttl;
-    messages.retain(|item| item.received_at >= cutoff);
-
-    if messages.len() > max_messages {
-        let keep_from = messages.len() - max_messages;
-        messages.drain(0..keep_from);
+impl MailboxMessages {
+    fn live(&self, cutoff: DateTime<Utc>) -> impl Iterator<Item = &Message> {
+        self.order
+            .iter()
+            .rev()
+            .filter_map(|id| self.by_id.get(id))
+            .take_while(move |item| item.received_at >= cutoff)
+    }
+}
+
+impl StoreInner {
+    fn insert(&mut self, mailbox: &str, message: Message) {
+        if let Some(message_id) = message.header("Message-ID").map(normalize_message_id) {
+            if !message_id.is_empty() {
+                self.by_message_id
+                    .entry(message_id)
+                    .or_default()
+                    .push((mailbox.to_string(), message.id.clone()));
+            }
+        }
+
+        let messages = self.by_mailbox.entry(mailbox.to_string()).or_default();
+        messages.order.push_back(message.id.clone());
+        messages.by_id.insert(message.id.clone(), message);
+    }
+
+    fn remove(&mut self, mailbox: &str, id: &str) -> Option<Message> {
+        let messages = self.by_mailbox.get_mut(mailbox)?;
+        let message = messages.by_id.remove(id)?;
+        if messages.order.front().is_some_and(|front| front == id) {
+            messages.order.pop_front();
+        } else {
+            messages.order.retain(|item| item != id);
+        }
+        if messages.by_id.is_empty() {
+            self.by_mailbox.remove(mailbox);
+        }
+        self.unindex(mailbox, &message);
+        Some(message)
+    }
+
+    fn remove_mailbox(&mut self, mailbox: &str) -> usize {
+        let Some(messages) = self.by_mailbox.remove(mailbox) else {
+            return 0;
+        };
+        for message in messages.by_id.values() {
+            self.unindex(mailbox, message);
+        }
+        messages.by_id.len()
     }
 
-    if !messages.is_empty() {
-        by_mailbox.insert(mailbox.to_string(), messages);
+    fn prune(&mut self, mailbox: &str, cutoff: DateTime<Utc>, max_messages: usize) -> usize {
+        let mut removed = 0;
+        while let Some(messages) = self.by_mailbox.get(mailbox) {
+            let Some(oldest) = messages.order.front() else {
+                break;
+            };
+            let expired = messages
+                .by_id
+                .get(oldest)
+                .is_none_or(|item| item.received_at < cutoff);
+            if !expired && messages.by_id.len() <= max_messages {
+                break;
+            }
+            let oldest = oldest.clone();
+            self.remove(mailbox, &oldest);
+            removed += 1;
+        }
+        removed
     }
+
+    fn unindex(&mut self, mailbox: &str, message: &Message) {
+        let Some(message_id) = message.header("Message-ID").map(normalize_message_id) else {
+            return;
+        };
+        if let Some(entries) = self.by_message_id.get_mut(&message_id) {
+            entries.retain(|(entry_mailbox, id)| entry_mailbox != mailbox || *id != message.id);
+            if entries.is_empty() {
+                self.by_message_id.remove(&message_id);
+            }
+        }
+    }
+}
+
+fn etag_matches(if_match: &str, message: &Message) -> bool {
+    let etag = message.etag();
+    if_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn normalize_message_id(value: &str) -> String {
+    value
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim()
+        .to_string()
 }
 
 fn build_preview(text: Option<&str>, html: Option<&str>) -> String {
@@ -0,0 +1,121 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::serve::Listener;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring::{self, cipher_suite};
+use tokio_rustls::rustls::{version, ServerConfig, SupportedCipherSuite};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+use crate::config::{Config, TlsCipherPolicy, TlsMinVersion};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Every ring suite is already ECDHE + AEAD; modern also drops AES-128-GCM.
+const MODERN_CIPHER_SUITES: &[SupportedCipherSuite] = &[
+    cipher_suite::TLS13_AES_256_GCM_SHA384,
+    cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+];
+
+pub fn load_server_config(cfg: &Config) -> anyhow::Result<Arc<ServerConfig>> {
+    let (cert_path, key_path) = (cfg.tls_cert_path.as_str(), cfg.tls_key_path.as_str());
+    if cert_path.is_empty() || key_path.is_empty() {
+        anyhow::bail!("TLS_CERT_PATH and TLS_KEY_PATH are required for TLS");
+    }
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|items| items.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read TLS certificates from {cert_path}"))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("failed to read TLS private key from {key_path}"))?;
+
+    let mut provider = ring::default_provider();
+    if cfg.tls_cipher_policy == TlsCipherPolicy::Modern {
+        provider.cipher_suites = MODERN_CIPHER_SUITES.to_vec();
+    }
+    let builder = ServerConfig::builder_with_provider(Arc::new(provider));
+    let builder = match cfg.tls_min_version {
+        TlsMinVersion::Tls12 => builder.with_safe_default_protocol_versions(),
+        TlsMinVersion::Tls13 => builder.with_protocol_versions(&[&version::TLS13]),
+    };
+    let config = builder
+        .context("failed to select TLS protocol versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+    Ok(Arc::new(config))
+}
+
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (accepted_tx, accepted) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    result = listener.accept() => match result {
+                        Ok(conn) => conn,
+                        Err(err) => {
+                            warn!("HTTPS accept failed: {}", err);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                    _ = accepted_tx.closed() => break,
+                };
+
+                let acceptor = acceptor.clone();
+                let accepted_tx = accepted_tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls_stream)) => {
+                            let _ = accepted_tx.send((tls_stream, peer)).await;
+                        }
+                        Ok(Err(err)) => warn!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            accepted,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(conn) => conn,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}